@@ -0,0 +1,7 @@
+/// Transitions of the sound timer, timestamped with the clk of the instruction
+/// that caused them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioEvent {
+    SoundOn,
+    SoundOff,
+}
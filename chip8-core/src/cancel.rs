@@ -0,0 +1,83 @@
+//! Shutdown of the CPU loop and the drivers started by [`crate::Chip8::run`].
+
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use crate::error::Chip8Error;
+
+#[derive(Debug, Default)]
+struct Shared {
+    reason: Option<Chip8Error>,
+    // Bumped by every notification, so that parked loops can tell they were
+    // woken up rather than timed out
+    wakeups: u64,
+}
+
+/// Shared by every loop of a run. The first loop to stop cancels it with the
+/// reason, which wakes up all the others parked on it.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A loop that panicked must not keep the others from stopping
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stops the run because of `reason`. An earlier reason is kept, unless
+    /// it was only a request to stop and `reason` is an error, so that a
+    /// driver failing while shutting down is still reported.
+    pub fn cancel(&self, reason: Chip8Error) {
+        let mut shared = self.lock();
+        let replace = match &shared.reason {
+            None => true,
+            Some(Chip8Error::Interrupt | Chip8Error::Stopped(_)) => {
+                !matches!(reason, Chip8Error::Interrupt | Chip8Error::Stopped(_))
+            }
+            Some(_) => false,
+        };
+        if replace {
+            shared.reason = Some(reason);
+        }
+        self.shared.1.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.lock().reason.is_some()
+    }
+
+    /// Why the run stopped, if it did.
+    pub fn reason(&self) -> Option<Chip8Error> {
+        self.lock().reason.clone()
+    }
+
+    /// Wakes up the loops parked on the token without stopping them, e.g.
+    /// when there is new input for the CPU.
+    pub fn notify(&self) {
+        self.lock().wakeups += 1;
+        self.shared.1.notify_all();
+    }
+
+    /// Sleeps for up to `timeout`, or until the token is cancelled or
+    /// notified. Returns true if it was notified.
+    pub fn park(&self, timeout: Duration) -> bool {
+        let shared = self.lock();
+        let wakeups = shared.wakeups;
+        let (shared, _) = self
+            .shared
+            .1
+            .wait_timeout_while(shared, timeout, |shared| {
+                shared.reason.is_none() && shared.wakeups == wakeups
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        shared.wakeups != wakeups
+    }
+}
@@ -1,94 +1,361 @@
 use std::{
-    collections::VecDeque,
-    sync::{Arc, RwLock},
+    fmt::Display,
+    sync::{Arc, RwLock, RwLockWriteGuard, TryLockError},
+    thread,
+    time::Duration,
 };
 
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
 use crate::{
+    cancel::CancellationToken,
+    control::{Control, StopConditions, StopReason},
     cpu::Cpu,
+    crash::{CrashReport, Trace},
     drivers::{AudioDriver, DisplayDriver, InputDriver},
     error::Chip8Error,
-    input::InputEvent,
-    rwlock::CheckedRead,
-    state::State,
+    fault::{FaultAction, FaultPolicy},
+    input::{InputEvent, InputSchedule},
+    movie::Checkpoints,
+    rwlock::{CheckedRead, CheckedWrite},
+    state::{Address, SaveState, State},
 };
 
+/// How long [`Chip8::run`] waits for the CPU loop and the drivers to stop once
+/// the run ends.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Stops the loops of a run whose future was dropped
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel(Chip8Error::Interrupt);
+    }
+}
+
+// Tells the run that a loop is done, and cancels it if the loop panicked,
+// which would otherwise leave the others running
+struct LoopGuard {
+    name: &'static str,
+    token: CancellationToken,
+    done: UnboundedSender<&'static str>,
+}
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let name = self.name;
+            self.token
+                .cancel(Chip8Error::AsyncAwaitError(format!("{name} loop panicked")));
+        }
+        let _ = self.done.send(self.name);
+    }
+}
+
+// Runs a loop on a thread of its own, which unlike tokio's blocking threads
+// can be left behind if it doesn't stop
+fn spawn_loop(
+    name: &'static str,
+    token: &CancellationToken,
+    done: &UnboundedSender<&'static str>,
+    f: impl FnOnce(CancellationToken) + Send + 'static,
+) -> Result<(), Chip8Error> {
+    let guard = LoopGuard {
+        name,
+        token: token.clone(),
+        done: done.clone(),
+    };
+    let token = token.clone();
+    thread::Builder::new()
+        .name(format!("chip8-{}", name.to_lowercase()))
+        .spawn(move || {
+            let _guard = guard;
+            f(token)
+        })
+        .map(drop)
+        .map_err(|e| Chip8Error::AsyncAwaitError(format!("Failed to start the {name} loop: {e}")))
+}
+
+/// How [`Chip8::run`] ended.
+#[derive(Debug, Clone)]
+pub enum ExitReason {
+    /// The input driver asked to quit
+    UserQuit,
+    Halted,
+    /// Waiting for a key with no input left, see [`StopConditions::until_input`]
+    WaitingForInput,
+    CycleLimit,
+    ReachedPc(Address),
+    /// An error in the CPU or a driver, with the instruction the machine
+    /// stopped at
+    Fault {
+        error: Chip8Error,
+        pc: Address,
+        opcode: u16,
+        clk: u64,
+    },
+}
+
+impl From<StopReason> for ExitReason {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::CycleLimit => Self::CycleLimit,
+            StopReason::Pc(pc) => Self::ReachedPc(pc),
+            StopReason::Halted => Self::Halted,
+            StopReason::WaitingForInput => Self::WaitingForInput,
+        }
+    }
+}
+
+impl Display for ExitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UserQuit => write!(f, "Quit"),
+            Self::Halted => write!(f, "Halted"),
+            Self::WaitingForInput => write!(f, "Waiting for input"),
+            Self::CycleLimit => write!(f, "Cycle limit reached"),
+            Self::ReachedPc(pc) => write!(f, "Reached PC 0x{pc:03X}"),
+            Self::Fault {
+                error,
+                pc,
+                opcode,
+                clk,
+            } => write!(
+                f,
+                "Fault at PC 0x{pc:03X} (opcode 0x{opcode:04X}, clk {clk}): {error}"
+            ),
+        }
+    }
+}
+
 pub struct Chip8<C>
 where
     C: Cpu,
 {
-    cpu: C,
-    input_queue: Arc<RwLock<VecDeque<(u64, InputEvent)>>>,
+    // Shared with the CPU loop's thread while running
+    cpu: Arc<RwLock<C>>,
+    input_queue: Arc<RwLock<InputSchedule>>,
+    control: Arc<RwLock<Control>>,
+    checkpoints: Arc<RwLock<Checkpoints>>,
+    trace: Arc<RwLock<Trace>>,
 }
 
 impl<C: Cpu> Chip8<C> {
     pub fn new(cpu: C, inputs: Vec<(u64, InputEvent)>) -> Self {
+        let control = Control::new(cpu.frequency());
         Self {
-            cpu,
-            input_queue: Arc::new(RwLock::new(VecDeque::from(inputs))),
+            cpu: Arc::new(RwLock::new(cpu)),
+            input_queue: Arc::new(RwLock::new(InputSchedule::new(inputs, vec![]))),
+            control: Arc::new(RwLock::new(control)),
+            checkpoints: Arc::new(RwLock::new(Checkpoints::default())),
+            trace: Arc::new(RwLock::new(Trace::default())),
+        }
+    }
+
+    // Nothing else holds the lock until the machine runs
+    fn with_control(self, f: impl FnOnce(&mut Control)) -> Self {
+        if let Ok(mut control) = self.control.write() {
+            f(&mut control);
+        }
+        self
+    }
+
+    /// Runs unthrottled until the clk reaches `clk`.
+    pub fn with_fast_forward(self, clk: u64) -> Self {
+        self.with_control(|control| control.fast_forward = clk)
+    }
+
+    /// Runs as fast as possible, like holding the turbo key.
+    pub fn with_turbo(self) -> Self {
+        self.with_control(|control| control.turbo = true)
+    }
+
+    /// Ends the run with [`Chip8Error::Stopped`] when one of `stop` is met.
+    pub fn with_stop_conditions(self, stop: StopConditions) -> Self {
+        self.with_control(|control| control.stop = stop)
+    }
+
+    /// Resets the machine when the clk reaches each of `resets`, as recorded
+    /// along with the inputs.
+    pub fn with_resets(self, resets: Vec<u64>) -> Self {
+        if let Ok(mut queue) = self.input_queue.write() {
+            for clk in resets {
+                queue.enqueue_reset(clk);
+            }
         }
+        self
+    }
+
+    /// Lets instructions fail without ending the run, see [`FaultPolicy`].
+    pub fn with_fault_policy(self, policy: FaultPolicy) -> Self {
+        self.with_control(|control| control.fault_policy = policy)
+    }
+
+    /// Takes a [`Checkpoints`] entry every `frames` frames, for the input
+    /// driver to record.
+    pub fn with_checkpoints(mut self, frames: u64) -> Self {
+        let Ok(ticks_per_timer) = self.cpu.read().map(|cpu| cpu.ticks_per_timer()) else {
+            return self;
+        };
+        // None would be due before the clk runs out anyway
+        let checkpoints = Checkpoints {
+            interval: frames.checked_mul(ticks_per_timer).unwrap_or(0),
+            ..Checkpoints::default()
+        };
+        self.checkpoints = Arc::new(RwLock::new(checkpoints));
+        self
     }
 
     pub fn load(&mut self, bytes: &[u8]) -> Result<(), Chip8Error> {
-        self.cpu.state().load_rom(bytes)
+        self.cpu()?.state().load_rom(bytes)
+    }
+
+    /// Resumes from `state` instead of power-on. The ROM should be loaded first
+    /// so that it is kept for resets.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), Chip8Error> {
+        let mut cpu = self.cpu()?;
+        cpu.state().load_state(state)?;
+        cpu.state().publish_snapshot()
+    }
+
+    pub fn save_state(&mut self) -> Result<SaveState, Chip8Error> {
+        self.cpu()?.state().save_state()
+    }
+
+    pub fn clk(&mut self) -> Result<u64, Chip8Error> {
+        self.cpu()?.state().clk()
+    }
+
+    /// Runs one cycle on the calling thread, without drivers. Queued input and
+    /// the fault policy are applied as usual.
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        let policy = self.control.checked_read()?.fault_policy;
+        let mut trace = self.trace.checked_write()?;
+        let mut cpu = self.cpu()?;
+        let step = cpu.step(&self.input_queue, &policy, &mut trace)?;
+        // Nothing resumes a pause here, so go on at once
+        if step.fault == Some(FaultAction::Pause) {
+            cpu.state().increment_program_counter();
+        }
+        Ok(())
     }
 
-    // TODO: Check if rt-multi-thread actually spawns separate threads
+    /// Runs until the CPU or one of the drivers stops, which cancels the
+    /// others. Together they get [`SHUTDOWN_TIMEOUT`] to wind down, after
+    /// which those still running are left behind and the run ends with
+    /// [`Chip8Error::ShutdownTimeout`].
+    ///
+    /// The CPU loop and the drivers run on threads of their own rather than
+    /// on the runtime, so that neither this future nor the runtime shutting
+    /// down waits on a loop that is stuck. Dropping the future stops them too.
     pub async fn run(
         &mut self,
         mut input: impl InputDriver + 'static,
         display: Option<impl DisplayDriver + 'static>,
         audio: Option<impl AudioDriver + 'static>,
-    ) -> Result<(), Chip8Error> {
-        // Status flag to check if machine is still running
-        let status = Arc::new(RwLock::new(Ok(())));
+    ) -> ExitReason
+    where
+        C: Send + Sync + 'static,
+    {
+        let token = CancellationToken::new();
+        let _guard = CancelOnDrop(token.clone());
+
+        let pointers = self.cpu().map(|mut cpu| {
+            let state = cpu.state();
+            (
+                state.clk_ptr(),
+                state.frame_buffer_ptr(),
+                state.snapshot_ptr(),
+                state.audio_queue_ptr(),
+            )
+        });
+        let (clk, frame_buffer, snapshot, audio_queue) = match pointers {
+            Ok(pointers) => pointers,
+            Err(error) => return self.exit_reason(Err(error)),
+        };
+
+        let (done, mut finished) = unbounded_channel();
+        let mut loops = vec![];
 
         // Input loop
-        let input_handle = {
-            let status = status.clone();
+        loops.push(("Input", {
             let queue = self.input_queue.clone();
-            let clk = self.cpu.state().clk_ptr();
+            let clk = clk.clone();
+            let control = self.control.clone();
+            let checkpoints = self.checkpoints.clone();
 
-            tokio::spawn(async move { input.run(status, queue, clk) })
-        };
+            spawn_loop("Input", &token, &done, move |token| {
+                input.run(token, queue, clk, control, checkpoints)
+            })
+        }));
         // Render loop
-        let display_handle = {
-            display.map(|mut display| {
-                let status = status.clone();
-                let frame_buffer = self.cpu.state().frame_buffer_ptr();
-                let clk = self.cpu.state().clk_ptr();
+        if let Some(mut display) = display {
+            let control = self.control.clone();
 
-                tokio::spawn(async move { display.run(status, frame_buffer, clk) })
-            })
-        };
+            loops.push((
+                "Display",
+                spawn_loop("Display", &token, &done, move |token| {
+                    display.run(token, frame_buffer, snapshot, control)
+                }),
+            ));
+        }
         // Audio loop
-        let audio_handle = {
-            audio.map(|mut audio| {
-                let status = status.clone();
-                let sound_timer = self.cpu.state().sound_timer_ptr();
+        if let Some(mut audio) = audio {
+            loops.push((
+                "Audio",
+                spawn_loop("Audio", &token, &done, move |token| {
+                    audio.run(token, audio_queue, clk)
+                }),
+            ));
+        }
+        // CPU loop
+        loops.push(("CPU", {
+            let cpu = self.cpu.clone();
+            let input_queue = self.input_queue.clone();
+            let control = self.control.clone();
+            let checkpoints = self.checkpoints.clone();
+            let trace = self.trace.clone();
 
-                tokio::spawn(async move { audio.run(status, sound_timer) })
+            spawn_loop("CPU", &token, &done, move |token| {
+                match cpu.checked_write() {
+                    Ok(mut cpu) => cpu.run(token, input_queue, control, checkpoints, trace),
+                    Err(error) => token.cancel(error),
+                }
             })
-        };
-        // CPU loop
-        self.cpu.run(status.clone(), self.input_queue.clone());
-
-        // Wait for all threads
-        input_handle
-            .await
-            .map_err(|e| Chip8Error::AsyncAwaitError(e.to_string()))?;
-        if let Some(display_handle) = display_handle {
-            display_handle
-                .await
-                .map_err(|e| Chip8Error::AsyncAwaitError(e.to_string()))?;
+        }));
+        drop(done);
+
+        let mut running = vec![];
+        for (name, spawned) in loops {
+            match spawned {
+                Ok(()) => running.push(name),
+                Err(error) => token.cancel(error),
+            }
+        }
+
+        // The first loop to return ends the run, if it didn't cancel it already
+        if let Some(name) = finished.recv().await {
+            running.retain(|running| *running != name);
+            token.cancel(Chip8Error::Interrupt);
         }
-        if let Some(audio_handle) = audio_handle {
-            audio_handle
-                .await
-                .map_err(|e| Chip8Error::AsyncAwaitError(e.to_string()))?;
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+        while !running.is_empty() {
+            match tokio::time::timeout_at(deadline, finished.recv()).await {
+                Ok(Some(name)) => running.retain(|running| *running != name),
+                Ok(None) => break,
+                Err(_) => {
+                    token.cancel(Chip8Error::ShutdownTimeout(format!(
+                        "{} loop did not stop within {SHUTDOWN_TIMEOUT:?}",
+                        running.join(", ")
+                    )));
+                    break;
+                }
+            }
         }
 
-        let res = status.checked_read()?;
-        res.clone()
+        let res = token.reason().map_or(Ok(()), Err);
+        self.exit_reason(res)
     }
 
     pub async fn load_and_run(
@@ -97,8 +364,79 @@ impl<C: Cpu> Chip8<C> {
         input: impl InputDriver + 'static,
         display: Option<impl DisplayDriver + 'static>,
         audio: Option<impl AudioDriver + 'static>,
-    ) -> Result<(), Chip8Error> {
+    ) -> Result<ExitReason, Chip8Error>
+    where
+        C: Send + Sync + 'static,
+    {
         self.load(rom)?;
-        self.run(input, display, audio).await
+        Ok(self.run(input, display, audio).await)
+    }
+
+    /// What the CPU loop did last, including the faults it let through.
+    pub fn trace(&self) -> Result<Trace, Chip8Error> {
+        let trace = self.trace.try_read().map_err(|e| match e {
+            TryLockError::WouldBlock => still_running(),
+            TryLockError::Poisoned(e) => Chip8Error::MutexReadError(e.to_string()),
+        })?;
+        Ok(trace.clone())
     }
+
+    /// The machine state and recent history for a run that ended in a fault.
+    pub fn crash_report(&mut self, reason: &ExitReason) -> Result<Option<CrashReport>, Chip8Error> {
+        let ExitReason::Fault {
+            error, pc, opcode, ..
+        } = reason
+        else {
+            return Ok(None);
+        };
+        // Not the program's fault, and the machine may still be changing
+        if matches!(error, Chip8Error::ShutdownTimeout(_)) {
+            return Ok(None);
+        }
+        Ok(Some(CrashReport {
+            error: error.clone(),
+            pc: *pc,
+            opcode: *opcode,
+            state: self.save_state()?,
+            rng_position: self.cpu()?.rng_position(),
+            trace: self.trace()?,
+        }))
+    }
+
+    // Nothing else holds the CPU outside of a run, unless its loop was left
+    // running past the shutdown deadline
+    fn cpu(&self) -> Result<RwLockWriteGuard<'_, C>, Chip8Error> {
+        self.cpu.try_write().map_err(|e| match e {
+            TryLockError::WouldBlock => still_running(),
+            TryLockError::Poisoned(e) => Chip8Error::MutexWriteError(e.to_string()),
+        })
+    }
+
+    // The CPU leaves the program counter on an instruction that failed
+    fn exit_reason(&mut self, res: Result<(), Chip8Error>) -> ExitReason {
+        match res {
+            Ok(()) | Err(Chip8Error::Interrupt) => ExitReason::UserQuit,
+            Err(Chip8Error::Stopped(reason)) => reason.into(),
+            Err(error) => {
+                let (pc, opcode, clk) = self
+                    .cpu()
+                    .map(|mut cpu| {
+                        let pc = cpu.state().program_counter();
+                        let opcode = cpu.peek().unwrap_or_default();
+                        (pc, opcode, cpu.state().clk().unwrap_or_default())
+                    })
+                    .unwrap_or_default();
+                ExitReason::Fault {
+                    error,
+                    pc,
+                    opcode,
+                    clk,
+                }
+            }
+        }
+    }
+}
+
+fn still_running() -> Chip8Error {
+    Chip8Error::ShutdownTimeout("The CPU loop is still running".to_string())
 }
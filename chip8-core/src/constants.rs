@@ -113,4 +113,5 @@ pub const FONTSET: [u8; NUM_FONTS * FONT_SIZE] = [
     0b10000000, // █
 ];
 
+/// Default number of cycles per timer tick.
 pub const TICKS_PER_TIMER: u64 = 8;
@@ -0,0 +1,180 @@
+use std::fmt::Display;
+
+use crate::{fault::FaultPolicy, idle::Activity, state::Address};
+
+/// Emulator actions requested by the host. Unlike [`crate::input::InputEvent`]s
+/// and resets these never reach the CHIP-8 and are not recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlEvent {
+    TogglePause,
+    /// Run exactly one frame (timer period) while paused
+    StepFrame,
+    SpeedUp,
+    SlowDown,
+    /// Run unthrottled while true
+    Turbo(bool),
+}
+
+/// Conditions that end a run on their own, e.g. for batch runs. None are set
+/// by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StopConditions {
+    /// Stop once the clk reaches this many cycles
+    pub max_cycles: Option<u64>,
+    /// Stop when the program counter reaches this address
+    pub until_pc: Option<Address>,
+    /// Stop when the program jumps to itself, as many ROMs do when done, or
+    /// loops in a way that only a reset can end
+    pub until_halt: bool,
+    /// Stop when the program waits for a key and no queued input is left, for
+    /// runs without a live keyboard
+    pub until_input: bool,
+}
+
+impl StopConditions {
+    pub fn is_empty(&self) -> bool {
+        self.max_cycles.is_none()
+            && self.until_pc.is_none()
+            && !self.until_halt
+            && !self.until_input
+    }
+}
+
+/// Which of the [`StopConditions`] ended the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    CycleLimit,
+    Pc(Address),
+    Halted,
+    WaitingForInput,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CycleLimit => write!(f, "cycle limit reached"),
+            Self::Pc(pc) => write!(f, "reached PC 0x{pc:03X}"),
+            Self::Halted => write!(f, "halted"),
+            Self::WaitingForInput => write!(f, "waiting for input"),
+        }
+    }
+}
+
+/// Runtime settings shared between the input driver, which changes them, and
+/// the CPU loop, which follows them.
+#[derive(Debug, Clone, Copy)]
+pub struct Control {
+    pub paused: bool,
+    /// Frames left to run while paused
+    pub step_frames: u64,
+    pub clk_freq: u64,
+    pub turbo: bool,
+    /// Run unthrottled until this clk, 0 if not fast-forwarding
+    pub fast_forward: u64,
+    pub stop: StopConditions,
+    pub fault_policy: FaultPolicy,
+    /// Set by the CPU loop when pausing on a fault at this address, see
+    /// [`crate::fault::FaultAction::Pause`]
+    pub paused_on_fault: Option<Address>,
+    /// Set by the CPU loop
+    pub activity: Activity,
+    /// Set by the CPU loop while running more cycles can't change anything,
+    /// i.e. the program is halted or waits for input that isn't queued
+    pub idle: bool,
+    /// Set by the input driver while it has input scheduled at later cycles,
+    /// see [`crate::drivers::InputDriver::is_scheduling`]
+    pub scheduled_input: bool,
+}
+
+impl Control {
+    pub fn new(clk_freq: u64) -> Self {
+        Self {
+            paused: false,
+            step_frames: 0,
+            clk_freq,
+            turbo: false,
+            fast_forward: 0,
+            stop: StopConditions::default(),
+            fault_policy: FaultPolicy::default(),
+            paused_on_fault: None,
+            activity: Activity::Running,
+            idle: false,
+            scheduled_input: false,
+        }
+    }
+
+    pub fn apply(&mut self, event: ControlEvent) {
+        match event {
+            ControlEvent::TogglePause => {
+                self.paused = !self.paused;
+                self.step_frames = 0;
+            }
+            ControlEvent::StepFrame => {
+                if self.paused {
+                    self.step_frames += 1;
+                }
+            }
+            ControlEvent::SpeedUp => {
+                self.clk_freq = self.clk_freq.saturating_mul(2);
+            }
+            ControlEvent::SlowDown => {
+                self.clk_freq = (self.clk_freq / 2).max(1);
+            }
+            ControlEvent::Turbo(turbo) => {
+                self.turbo = turbo;
+            }
+        }
+    }
+
+    /// Returns true if the CPU should execute cycles.
+    pub fn is_running(&self) -> bool {
+        !self.paused || self.step_frames > 0
+    }
+
+    /// Whether the CPU loop may stop running cycles until the input driver
+    /// changes something: the program is idle and nothing else counts on the
+    /// clk moving.
+    pub fn can_park(&self) -> bool {
+        self.idle && !self.counts_cycles()
+    }
+
+    // Scheduled input, fast-forwarding and cycle limits all wait for a clk
+    // that idle cycles still get closer to
+    fn counts_cycles(&self) -> bool {
+        self.scheduled_input || self.fast_forward > 0 || self.stop.max_cycles.is_some()
+    }
+
+    /// Frequency the CPU loop should run at, 0 meaning unthrottled. Turbo is
+    /// dropped while idle, rather than spinning through cycles that do nothing,
+    /// unless something counts them. An idle program whose timers have
+    /// stopped doesn't run at all, see [`Self::can_park`].
+    pub fn frequency(&self) -> u64 {
+        let turbo = self.turbo && (!self.idle || self.counts_cycles());
+        if (turbo || self.fast_forward > 0) && !self.paused {
+            0
+        } else {
+            self.clk_freq
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_turbo_while_idle_cycles_count() {
+        let mut control = Control::new(500);
+        control.turbo = true;
+        control.idle = true;
+        assert!(control.can_park());
+        assert_eq!(control.frequency(), 500);
+
+        control.stop.max_cycles = Some(100_000);
+        assert!(!control.can_park());
+        assert_eq!(control.frequency(), 0);
+
+        control.paused = true;
+        assert_eq!(control.frequency(), 500);
+    }
+}
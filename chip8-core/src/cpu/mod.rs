@@ -1,21 +1,58 @@
 use std::{
-    collections::VecDeque,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 mod simple;
-pub use simple::SimpleCpu;
+pub use simple::{SeekableRng, SimpleCpu};
 
 use crate::{
-    constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH, FONTSET_START_ADDRESS, FONT_SIZE, TICKS_PER_TIMER},
+    audio::AudioEvent,
+    cancel::CancellationToken,
+    constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH, FONTSET_START_ADDRESS, FONT_SIZE, MEMORY_SIZE},
+    control::{Control, StopReason},
+    crash::Trace,
     error::Chip8Error,
-    input::{InputEvent, InputQueue},
+    fault::{FaultAction, FaultPolicy, SuppressedFault},
+    idle::{Activity, IdleDetector},
+    input::{InputQueue, InputSchedule},
     instruction::Instruction,
+    movie::Checkpoints,
     rwlock::{CheckedRead, CheckedWrite},
     state::{Address, State, Word},
-    util::run_loop,
+    util::run_loop_dynamic,
 };
 
+/// What [`Cpu::step`] ran.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    /// Address of the instruction
+    pub pc: Address,
+    /// None if it failed, and the fault was let through
+    pub instruction: Option<Instruction>,
+    /// What was done about it failing
+    pub fault: Option<FaultAction>,
+    /// Whether the machine was reset before it
+    pub reset: bool,
+}
+
+// Instructions touching `len` bytes from `addr` check them all up front, so
+// that one which fails leaves the machine as it was. Offsets from `addr` below
+// `len` can't overflow afterwards.
+fn check_memory(addr: Address, len: usize) -> Result<(), Chip8Error> {
+    if len > 0 && addr as usize + len > MEMORY_SIZE {
+        Err(Chip8Error::MemoryAccessOutOfBounds(
+            addr.max(MEMORY_SIZE as Address),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Longest the CPU loop stays parked without looking for input, in case it
+/// missed a wakeup.
+const PARK_TIMEOUT: Duration = Duration::from_millis(16);
+
 pub trait Cpu {
     type State: State;
 
@@ -23,8 +60,16 @@ pub trait Cpu {
 
     fn frequency(&self) -> u64;
 
+    fn set_frequency(&mut self, frequency: u64);
+
+    /// Cycles per 60Hz timer tick, i.e. per frame.
+    fn ticks_per_timer(&self) -> u64;
+
     fn random(&mut self) -> Word;
 
+    /// Where the generator behind [`Self::random`] is in its stream.
+    fn rng_position(&self) -> u64;
+
     // Instructions
     fn op_clear_display(&mut self) -> Result<(), Chip8Error> {
         self.state().clear_framebuffer()
@@ -174,6 +219,7 @@ pub trait Cpu {
         let vy = self.state().register(y);
         let vi = self.state().index_register();
 
+        check_memory(vi, n as usize)?;
         let x0 = vx as usize % DISPLAY_WIDTH;
         let y0 = vy as usize % DISPLAY_HEIGHT;
         let mut flipped = false;
@@ -191,6 +237,7 @@ pub trait Cpu {
             }
         }
         self.state().set_flag_register(flipped);
+        self.state().increment_draw_count();
         Ok(())
     }
 
@@ -213,21 +260,14 @@ pub trait Cpu {
         self.state().set_register(x, val);
     }
 
-    fn op_wait_key_press(
-        &mut self,
-        status: Arc<RwLock<Result<(), Chip8Error>>>,
-        input_queue: Arc<RwLock<VecDeque<(u64, InputEvent)>>>,
-        x: Word,
-    ) -> Result<(), Chip8Error> {
-        let clk = self.state().clk()?;
-        while status.checked_read()?.is_ok() {
-            if let Some(event) = (*input_queue.checked_write()?).dequeue(clk) {
-                self.state().set_key(event.key, event.kind);
-                self.state().set_register(x, event.key as u8);
-                break;
-            }
+    // Re-executed every cycle until a key is pressed, so that timers and the
+    // clk keep running while waiting. Once nothing but input can end the wait,
+    // the CPU loop parks instead, see `Cpu::run`.
+    fn op_wait_key_press(&mut self, x: Word) {
+        match self.state().take_key_press() {
+            Some(key) => self.state().set_register(x, key as u8),
+            None => self.state().rewind_program_counter(),
         }
-        Ok(())
     }
 
     fn op_set_delay(&mut self, x: Word) {
@@ -237,7 +277,14 @@ pub trait Cpu {
 
     fn op_set_sound(&mut self, x: Word) -> Result<(), Chip8Error> {
         let vx = self.state().register(x);
-        self.state().set_sound_timer(vx)
+        let st = self.state().sound_timer();
+        self.state().set_sound_timer(vx);
+
+        match (st > 0, vx > 0) {
+            (false, true) => self.emit_audio(AudioEvent::SoundOn),
+            (true, false) => self.emit_audio(AudioEvent::SoundOff),
+            _ => Ok(()),
+        }
     }
 
     fn op_add_i(&mut self, x: Word) {
@@ -256,6 +303,7 @@ pub trait Cpu {
     fn op_store_bcd(&mut self, x: Word) -> Result<(), Chip8Error> {
         let vx = self.state().register(x);
         let vi = self.state().index_register();
+        check_memory(vi, 3)?;
 
         self.state().set_memory(vi, (vx / 100) % 10)?;
         self.state().set_memory(vi + 1, (vx / 10) % 10)?;
@@ -264,6 +312,7 @@ pub trait Cpu {
 
     fn op_store_registers(&mut self, x: Word) -> Result<(), Chip8Error> {
         let vi = self.state().index_register();
+        check_memory(vi, x as usize + 1)?;
         for j in 0..=x {
             let vj = self.state().register(j);
             self.state().set_memory(vi + j as u16, vj)?;
@@ -273,6 +322,7 @@ pub trait Cpu {
 
     fn op_load_memory(&mut self, x: Word) -> Result<(), Chip8Error> {
         let vi = self.state().index_register();
+        check_memory(vi, x as usize + 1)?;
         for j in 0..=x {
             let val = self.state().memory(vi + j as u16)?;
             self.state().set_register(j, val);
@@ -282,119 +332,27 @@ pub trait Cpu {
 
     // Fetch - Decode - Execute
     fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let opcode = self.peek()?;
+        self.state().increment_program_counter();
+        Ok(opcode)
+    }
+
+    /// The opcode at the program counter, without moving past it.
+    fn peek(&mut self) -> Result<u16, Chip8Error> {
         let pc = self.state().program_counter();
         let hi = self.state().memory(pc)?;
-        let lo = self.state().memory(pc + 1)?;
-
-        self.state().increment_program_counter();
+        let next = pc
+            .checked_add(1)
+            .ok_or(Chip8Error::MemoryAccessOutOfBounds(pc))?;
+        let lo = self.state().memory(next)?;
         Ok(u16::from_be_bytes([hi, lo]))
     }
 
     fn decode(&mut self, opcode: u16) -> Result<Instruction, Chip8Error> {
-        let x = ((opcode >> 8) & 0x000F) as u8;
-        let y = ((opcode >> 4) & 0x000F) as u8;
-
-        let n = (opcode & 0x000F) as u8;
-        let nn = (opcode & 0x00FF) as u8;
-        let nnn = opcode & 0x0FFF;
-
-        match opcode & 0xF000 {
-            0x0000 => match opcode & 0xF0FF {
-                // 0x00E0
-                0x00E0 => Ok(Instruction::ClearDisplay),
-                // 0x00EE
-                0x00EE => Ok(Instruction::Return),
-                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-            },
-            // 0x1NNN
-            0x1000 => Ok(Instruction::Jump(nnn)),
-            // 0x2NNN
-            0x2000 => Ok(Instruction::Call(nnn)),
-            // 0x3XNN
-            0x3000 => Ok(Instruction::SkipEqual(x, nn)),
-            // 0x4XNN
-            0x4000 => Ok(Instruction::SkipNotEqual(x, nn)),
-            // 0x5XY0
-            0x5000 => match opcode & 0xF00F {
-                0x5000 => Ok(Instruction::SkipEqualXY(x, y)),
-                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-            },
-            // 0x6XNN
-            0x6000 => Ok(Instruction::Load(x, nn)),
-            // 0x7XNN
-            0x7000 => Ok(Instruction::Add(x, nn)),
-            0x8000 => match opcode & 0xF00F {
-                // 0x8XY0
-                0x8000 => Ok(Instruction::Move(x, y)),
-                // 0x8XY1
-                0x8001 => Ok(Instruction::Or(x, y)),
-                // 0x8XY2
-                0x8002 => Ok(Instruction::And(x, y)),
-                // 0x8XY3
-                0x8003 => Ok(Instruction::Xor(x, y)),
-                // 0x8XY4
-                0x8004 => Ok(Instruction::AddXY(x, y)),
-                // 0x8XY5
-                0x8005 => Ok(Instruction::SubXY(x, y)),
-                // 0x8XY6
-                0x8006 => Ok(Instruction::ShiftRight(x)),
-                // 0x8XY7
-                0x8007 => Ok(Instruction::SubYX(x, y)),
-                // 0x8XYE
-                0x800E => Ok(Instruction::ShiftLeft(x)),
-                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-            },
-            0x9000 => match opcode & 0xF00F {
-                // 0x9XY0
-                0x9000 => Ok(Instruction::SkipNotEqualXY(x, y)),
-                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-            },
-            // 0xANNN
-            0xA000 => Ok(Instruction::LoadI(nnn)),
-            // 0xBNNN
-            0xB000 => Ok(Instruction::JumpV0(nnn)),
-            // 0xCXNN
-            0xC000 => Ok(Instruction::Random(x, nn)),
-            // 0xDXYN
-            0xD000 => Ok(Instruction::Draw(x, y, n)),
-            0xE000 => match opcode & 0xF0FF {
-                // 0xEX9E
-                0xE09E => Ok(Instruction::SkipKeyPressed(x)),
-                // 0xEXA1
-                0xE0A1 => Ok(Instruction::SkipKeyNotPressed(x)),
-                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-            },
-            0xF000 => match opcode & 0xF0FF {
-                // 0xFX07
-                0xF007 => Ok(Instruction::LoadDelay(x)),
-                // 0xFX0A
-                0xF00A => Ok(Instruction::WaitKeyPress(x)),
-                // 0xFX15
-                0xF015 => Ok(Instruction::SetDelay(x)),
-                // 0xFX18
-                0xF018 => Ok(Instruction::SetSound(x)),
-                // 0xFX1E
-                0xF01E => Ok(Instruction::AddI(x)),
-                // 0xFX29
-                0xF029 => Ok(Instruction::LoadFont(x)),
-                // 0xFX33
-                0xF033 => Ok(Instruction::StoreBCD(x)),
-                // 0xFX55
-                0xF055 => Ok(Instruction::StoreRegisters(x)),
-                // 0xFX65
-                0xF065 => Ok(Instruction::LoadMemory(x)),
-                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-            },
-            _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
-        }
+        Instruction::try_from(opcode)
     }
 
-    fn execute(
-        &mut self,
-        instruction: Instruction,
-        status: Arc<RwLock<Result<(), Chip8Error>>>,
-        input_queue: Arc<RwLock<VecDeque<(u64, InputEvent)>>>,
-    ) -> Result<(), Chip8Error> {
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
         match instruction {
             Instruction::ClearDisplay => {
                 self.op_clear_display()?;
@@ -475,7 +433,7 @@ pub trait Cpu {
                 self.op_load_delay(x);
             }
             Instruction::WaitKeyPress(x) => {
-                self.op_wait_key_press(status, input_queue, x)?;
+                self.op_wait_key_press(x);
             }
             Instruction::SetDelay(x) => {
                 self.op_set_delay(x);
@@ -504,45 +462,320 @@ pub trait Cpu {
     }
 
     // Cycle
-    fn tick(
-        &mut self,
-        status: Arc<RwLock<Result<(), Chip8Error>>>,
-        input_queue: Arc<RwLock<VecDeque<(u64, InputEvent)>>>,
-    ) -> Result<(), Chip8Error> {
+    fn tick(&mut self) -> Result<Instruction, Chip8Error> {
         let op = self.fetch()?;
         let instruction = self.decode(op)?;
-        self.execute(instruction, status, input_queue)
+        self.execute(instruction)?;
+        Ok(instruction)
     }
 
     fn tick_timers(&mut self) -> Result<(), Chip8Error> {
         if self.state().delay_timer() > 0 {
             self.state().decrement_delay_timer();
         }
-        if self.state().sound_timer()? > 0 {
-            self.state().decrement_sound_timer()?;
+        if self.state().sound_timer() > 0 {
+            self.state().decrement_sound_timer();
+            if self.state().sound_timer() == 0 {
+                self.emit_audio(AudioEvent::SoundOff)?;
+            }
         }
         Ok(())
     }
 
+    // Sound timer transitions are timestamped with the clk of the current cycle
+    fn emit_audio(&mut self, event: AudioEvent) -> Result<(), Chip8Error> {
+        let clk = self.state().clk()?;
+        self.state().enqueue_audio(clk, event)
+    }
+
+    /// Reloads the ROM into a fresh state. The clk keeps counting so that
+    /// drivers see it increase monotonically.
+    fn reset(&mut self) -> Result<(), Chip8Error> {
+        if self.state().sound_timer() > 0 {
+            self.emit_audio(AudioEvent::SoundOff)?;
+        }
+        self.state().reset()?;
+        self.state().publish_snapshot()
+    }
+
+    /// One cycle, including pending input and timers, recorded in `trace`.
+    /// An instruction that fails ends the cycle with its error, unless
+    /// `policy` lets the fault through. On a pause the program counter stays
+    /// on the instruction, for the caller to skip when resuming.
+    fn step(
+        &mut self,
+        input_queue: &RwLock<InputSchedule>,
+        policy: &FaultPolicy,
+        trace: &mut Trace,
+    ) -> Result<Step, Chip8Error> {
+        let clk = self.state().clk()?;
+        let mut queue = input_queue.checked_write()?;
+        // A reset is input too, and the cycle it is due at runs from the ROM
+        let reset = queue.dequeue_reset(clk);
+        if reset {
+            self.reset()?;
+        }
+        let pc = self.state().program_counter();
+        // A fetch that fails fails again in `tick`, where `policy` applies
+        let fetched = self.peek().ok();
+        if let Some(opcode) = fetched {
+            trace.record_instruction(clk, pc, opcode);
+        }
+        let opcode = fetched.unwrap_or_default();
+
+        self.apply_input(&mut queue, trace)?;
+        drop(queue);
+        let (instruction, fault) = match self.tick() {
+            Ok(instruction) => (Some(instruction), None),
+            Err(error) => {
+                // Point at the failed instruction rather than past it
+                self.state().set_program_counter(pc);
+                let action = policy.action(&error);
+                if action == FaultAction::Abort {
+                    return Err(error);
+                }
+                trace.record_fault(SuppressedFault {
+                    clk,
+                    pc,
+                    opcode,
+                    error,
+                    action,
+                });
+                // A pause stays on the instruction until resumed
+                if action != FaultAction::Pause {
+                    self.state().increment_program_counter();
+                }
+                (None, Some(action))
+            }
+        };
+        self.finish_cycle()?;
+
+        Ok(Step {
+            pc,
+            instruction,
+            fault,
+            reset,
+        })
+    }
+
+    // Input queued up to the current cycle
+    fn apply_input(
+        &mut self,
+        input_queue: &mut InputSchedule,
+        trace: &mut Trace,
+    ) -> Result<(), Chip8Error> {
+        let clk = self.state().clk()?;
+
+        // Only presses from this cycle can end a wait for a key
+        self.state().take_key_press();
+        while let Some(event) = input_queue.dequeue(clk) {
+            self.state().set_key(event.key, event.kind);
+            trace.record_input(clk, event);
+        }
+        Ok(())
+    }
+
+    // Timers and clk, once the instruction of the cycle has run
+    fn finish_cycle(&mut self) -> Result<(), Chip8Error> {
+        let clk = self.state().clk()?;
+        if clk.is_multiple_of(self.ticks_per_timer()) {
+            self.tick_timers()?;
+        }
+
+        self.state().increment_clk()?;
+        self.state().publish_snapshot()
+    }
+
+    /// Runs cycles until `token` is cancelled. While paused, or while only
+    /// input can change anything, the loop parks on the token instead, to be
+    /// woken up by the input driver. The loop keeps its own copy of `trace`
+    /// and hands it back when it ends.
     fn run(
         &mut self,
-        status: Arc<RwLock<Result<(), Chip8Error>>>,
-        input_queue: Arc<RwLock<VecDeque<(u64, InputEvent)>>>,
+        token: CancellationToken,
+        input_queue: Arc<RwLock<InputSchedule>>,
+        control: Arc<RwLock<Control>>,
+        checkpoints: Arc<RwLock<Checkpoints>>,
+        trace: Arc<RwLock<Trace>>,
     ) {
-        run_loop(status.clone(), self.frequency(), move |_| {
-            let clk = self.state().clk()?;
+        let frequency = {
+            let control = control.clone();
+            move || Ok(control.checked_read()?.frequency())
+        };
+
+        let mut detector = IdleDetector::default();
+        let waker = token.clone();
+        let mut local = match trace.checked_read() {
+            Ok(trace) => trace.clone(),
+            Err(error) => return token.cancel(error),
+        };
+        let recorded = &mut local;
+        run_loop_dynamic(&token, frequency, move |_| {
+            let current = *control.checked_read()?;
+            if current.clk_freq != self.frequency() {
+                self.set_frequency(current.clk_freq);
+            }
+            // The clk stands still while parked, which only the timers and
+            // input scheduled at later cycles could tell apart
+            let timers_stopped = self.state().delay_timer() == 0 && self.state().sound_timer() == 0;
+            let parked =
+                current.can_park() && timers_stopped && input_queue.checked_read()?.is_empty();
+            if !current.is_running() || parked {
+                waker.park(PARK_TIMEOUT);
+                return Ok(());
+            }
+
+            // Resumed after pausing on a fault, which is skipped unless the
+            // program went elsewhere meanwhile, e.g. through a reset
+            if let Some(pc) = current.paused_on_fault {
+                if self.state().program_counter() == pc {
+                    self.state().increment_program_counter();
+                }
+                control.checked_write()?.paused_on_fault = None;
+            }
 
-            while let Some(event) = (*input_queue.checked_write()?).dequeue(clk) {
-                self.state().set_key(event.key, event.kind);
+            let step = self.step(&input_queue, &current.fault_policy, recorded)?;
+            if step.reset {
+                detector = IdleDetector::default();
+            }
+            if step.fault == Some(FaultAction::Pause) {
+                let mut control = control.checked_write()?;
+                control.paused = true;
+                control.step_frames = 0;
+                control.paused_on_fault = Some(step.pc);
             }
-            // TODO: How do I remove this clone?
-            self.tick(status.clone(), input_queue.clone())?;
-            if clk % TICKS_PER_TIMER == 0 {
-                self.tick_timers()?;
+            if let Some(instruction) = &step.instruction {
+                detector.observe(step.pc, instruction, self.state());
             }
 
-            self.state().increment_clk()?;
+            // Only input can get a program out of waiting for a key
+            let activity = detector.activity();
+            let idle = match activity {
+                Activity::Halted => true,
+                Activity::WaitingForKey => input_queue.checked_read()?.is_empty(),
+                Activity::Running | Activity::WaitingForTimer => false,
+            };
+            if activity != current.activity || idle != current.idle {
+                let mut control = control.checked_write()?;
+                control.activity = activity;
+                control.idle = idle;
+            }
+
+            let clk = self.state().clk()?;
+            if current.fast_forward > 0 && clk >= current.fast_forward {
+                control.checked_write()?.fast_forward = 0;
+            }
+            if checkpoints.checked_read()?.is_due(clk) {
+                let checksum = self.state().save_state()?.checksum();
+                checkpoints
+                    .checked_write()?
+                    .pending
+                    .push_back((clk, checksum));
+            }
+
+            // A frame ends when the next cycle ticks the timers
+            if current.paused && clk.is_multiple_of(self.ticks_per_timer()) {
+                let mut control = control.checked_write()?;
+                control.step_frames = control.step_frames.saturating_sub(1);
+            }
+
+            if current.stop.max_cycles.is_some_and(|max| clk >= max) {
+                return Err(Chip8Error::Stopped(StopReason::CycleLimit));
+            }
+            let pc = self.state().program_counter();
+            if current.stop.until_pc == Some(pc) {
+                return Err(Chip8Error::Stopped(StopReason::Pc(pc)));
+            }
+            if current.stop.until_halt && activity == Activity::Halted {
+                return Err(Chip8Error::Stopped(StopReason::Halted));
+            }
+            if current.stop.until_input && activity == Activity::WaitingForKey && idle {
+                return Err(Chip8Error::Stopped(StopReason::WaitingForInput));
+            }
             Ok(())
-        })
+        });
+        match trace.checked_write() {
+            Ok(mut trace) => *trace = local,
+            Err(error) => token.cancel(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+
+    use super::*;
+    use crate::fault::FaultKind;
+
+    // 0000 is not implemented, then V0 = 5
+    const ROM: [u8; 4] = [0x00, 0x00, 0x60, 0x05];
+
+    fn cpu(pc: Address) -> SimpleCpu<ChaCha12Rng> {
+        let mut cpu = SimpleCpu::new(500, ChaCha12Rng::seed_from_u64(0));
+        cpu.state().load_rom(&ROM).unwrap();
+        cpu.state().set_program_counter(pc);
+        cpu
+    }
+
+    fn step(
+        cpu: &mut SimpleCpu<ChaCha12Rng>,
+        policy: FaultPolicy,
+        trace: &mut Trace,
+    ) -> Result<Step, Chip8Error> {
+        let queue = RwLock::new(InputSchedule::new(vec![], vec![]));
+        cpu.step(&queue, &policy, trace)
+    }
+
+    #[test]
+    fn applies_each_fault_action() {
+        let mut trace = Trace::default();
+        let mut aborted = cpu(0x200);
+        assert!(matches!(
+            step(&mut aborted, FaultPolicy::default(), &mut trace),
+            Err(Chip8Error::UnimplementedOpcode(0))
+        ));
+        assert_eq!(aborted.state().program_counter(), 0x200);
+        assert_eq!(trace.suppressed, 0);
+
+        for (action, next) in [
+            (FaultAction::Skip, 0x202),
+            (FaultAction::Nop, 0x202),
+            (FaultAction::Pause, 0x200),
+        ] {
+            let mut trace = Trace::default();
+            let mut let_through = cpu(0x200);
+            let faulted = step(&mut let_through, FaultPolicy::all(action), &mut trace).unwrap();
+            assert_eq!(faulted.pc, 0x200);
+            assert!(faulted.instruction.is_none());
+            assert_eq!(faulted.fault, Some(action));
+            assert_eq!(let_through.state().program_counter(), next, "{action}");
+            assert_eq!(let_through.state().clk().unwrap(), 1);
+            assert_eq!(trace.suppressed, 1);
+            assert_eq!(trace.faults[0].action, action);
+        }
+    }
+
+    #[test]
+    fn lets_failed_fetches_through_the_policy() {
+        let mut policy = FaultPolicy::default();
+        policy.set(FaultKind::MemoryAccessOutOfBounds, FaultAction::Nop);
+
+        let mut trace = Trace::default();
+        let mut let_through = cpu(MEMORY_SIZE as Address - 1);
+        let fetched = step(&mut let_through, policy, &mut trace).unwrap();
+        assert_eq!(fetched.fault, Some(FaultAction::Nop));
+        assert!(trace.instructions.is_empty());
+        assert!(matches!(
+            trace.faults[0].error,
+            Chip8Error::MemoryAccessOutOfBounds(_)
+        ));
+
+        let mut aborted = cpu(MEMORY_SIZE as Address - 1);
+        assert!(matches!(
+            step(&mut aborted, FaultPolicy::default(), &mut trace),
+            Err(Chip8Error::MemoryAccessOutOfBounds(_))
+        ));
     }
 }
@@ -1,26 +1,63 @@
-use rand::Rng;
+use rand::{Rng, RngCore};
+use rand_chacha::ChaCha12Rng;
 
 use super::Cpu;
-use crate::state::{SimpleState, Word};
+use crate::{
+    constants::TICKS_PER_TIMER,
+    state::{SimpleState, Word},
+};
 
-pub struct SimpleCpu<R: Rng> {
+/// A random number generator that can tell where it is in its stream and jump
+/// anywhere in it.
+pub trait SeekableRng: RngCore {
+    fn position(&self) -> u64;
+
+    fn seek(&mut self, position: u64);
+}
+
+impl SeekableRng for ChaCha12Rng {
+    // In 32-bit words, of which one random byte takes one
+    fn position(&self) -> u64 {
+        u64::try_from(self.get_word_pos()).unwrap_or(u64::MAX)
+    }
+
+    fn seek(&mut self, position: u64) {
+        self.set_word_pos(position.into());
+    }
+}
+
+pub struct SimpleCpu<R: SeekableRng> {
     // TODO: Make private
     pub state: SimpleState,
     pub clk_freq: u64,
+    pub ticks_per_timer: u64,
     pub rng: R,
 }
 
-impl<R: Rng> SimpleCpu<R> {
+impl<R: SeekableRng> SimpleCpu<R> {
     pub fn new(clk_freq: u64, rng: R) -> Self {
         Self {
             state: SimpleState::default(),
             clk_freq,
+            ticks_per_timer: TICKS_PER_TIMER,
             rng,
         }
     }
+
+    pub fn with_ticks_per_timer(mut self, ticks_per_timer: u64) -> Self {
+        self.ticks_per_timer = ticks_per_timer.max(1);
+        self
+    }
+
+    /// Moves the generator to `position`, to pick up where a recorded machine
+    /// left it.
+    pub fn with_rng_position(mut self, position: u64) -> Self {
+        self.rng.seek(position);
+        self
+    }
 }
 
-impl<R: Rng> Cpu for SimpleCpu<R> {
+impl<R: SeekableRng> Cpu for SimpleCpu<R> {
     type State = SimpleState;
 
     fn state(&mut self) -> &mut Self::State {
@@ -31,7 +68,19 @@ impl<R: Rng> Cpu for SimpleCpu<R> {
         self.rng.gen()
     }
 
+    fn rng_position(&self) -> u64 {
+        self.rng.position()
+    }
+
     fn frequency(&self) -> u64 {
         self.clk_freq
     }
+
+    fn set_frequency(&mut self, frequency: u64) {
+        self.clk_freq = frequency;
+    }
+
+    fn ticks_per_timer(&self) -> u64 {
+        self.ticks_per_timer
+    }
 }
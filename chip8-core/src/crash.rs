@@ -0,0 +1,159 @@
+//! Crash reports for runs that end in a fault.
+
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::{
+    constants::MEMORY_SIZE,
+    error::Chip8Error,
+    fault::SuppressedFault,
+    input::{InputEvent, InputKind},
+    instruction::disassemble,
+    state::{Address, SaveState},
+};
+
+/// Executed instructions kept by [`Trace`].
+pub const TRACE_INSTRUCTIONS: usize = 32;
+/// Input events kept by [`Trace`].
+pub const TRACE_INPUTS: usize = 16;
+/// Suppressed faults kept by [`Trace`].
+pub const TRACE_FAULTS: usize = 16;
+/// Instructions disassembled on each side of the faulting one.
+const CONTEXT: u16 = 6;
+
+/// Ring buffers of what the CPU loop did last.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    /// Clk, address and opcode of the last instructions, oldest first
+    pub instructions: VecDeque<(u64, Address, u16)>,
+    /// Last input events applied, with the clk they were applied at
+    pub inputs: VecDeque<(u64, InputEvent)>,
+    /// Last faults that the [`crate::fault::FaultPolicy`] let through
+    pub faults: VecDeque<SuppressedFault>,
+    /// Number of faults let through in total
+    pub suppressed: u64,
+}
+
+impl Trace {
+    pub fn record_instruction(&mut self, clk: u64, pc: Address, opcode: u16) {
+        push_bounded(
+            &mut self.instructions,
+            TRACE_INSTRUCTIONS,
+            (clk, pc, opcode),
+        );
+    }
+
+    pub fn record_input(&mut self, clk: u64, event: InputEvent) {
+        push_bounded(&mut self.inputs, TRACE_INPUTS, (clk, event));
+    }
+
+    pub fn record_fault(&mut self, fault: SuppressedFault) {
+        self.suppressed += 1;
+        push_bounded(&mut self.faults, TRACE_FAULTS, fault);
+    }
+}
+
+fn push_bounded<T>(ring: &mut VecDeque<T>, len: usize, item: T) {
+    if ring.len() == len {
+        ring.pop_front();
+    }
+    ring.push_back(item);
+}
+
+/// Everything known about a fault, from [`crate::Chip8::crash_report`].
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    pub error: Chip8Error,
+    pub pc: Address,
+    pub opcode: u16,
+    /// The machine at the start of the faulting instruction, with the input
+    /// of its cycle applied. A failed instruction changes nothing, so running
+    /// from here reproduces the fault.
+    pub state: SaveState,
+    /// Where the random number generator was, for a replay from `state` to
+    /// draw the same numbers.
+    pub rng_position: u64,
+    pub trace: Trace,
+}
+
+impl CrashReport {
+    /// Address, opcode and disassembly of the instructions around the fault.
+    pub fn disassembly(&self) -> Vec<(Address, u16, String)> {
+        let first = self.pc.saturating_sub(2 * CONTEXT);
+        (first..=self.pc.saturating_add(2 * CONTEXT))
+            .step_by(2)
+            .filter(|&addr| (addr as usize) + 1 < MEMORY_SIZE)
+            .map(|addr| {
+                let hi = self.state.memory[addr as usize];
+                let lo = self.state.memory[addr as usize + 1];
+                let opcode = u16::from_be_bytes([hi, lo]);
+                (addr, opcode, disassemble(opcode))
+            })
+            .collect()
+    }
+}
+
+impl Display for CrashReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = &self.state;
+        writeln!(f, "Fault: {}", self.error)?;
+        writeln!(
+            f,
+            "PC: 0x{:03X}  Opcode: 0x{:04X}  Clk: {}",
+            self.pc, self.opcode, state.clk
+        )?;
+
+        writeln!(f, "\nDisassembly:")?;
+        for (addr, opcode, text) in self.disassembly() {
+            let marker = if addr == self.pc { '>' } else { ' ' };
+            writeln!(f, "{marker} 0x{addr:03X}  {opcode:04X}  {text}")?;
+        }
+
+        writeln!(f, "\nRegisters:")?;
+        for (row, registers) in state.registers.chunks(8).enumerate() {
+            let values = registers.iter().map(|v| format!("{v:02X}"));
+            let values = values.collect::<Vec<_>>().join(" ");
+            writeln!(f, "V{:X}-V{:X}: {values}", row * 8, row * 8 + 7)?;
+        }
+        writeln!(
+            f,
+            "I: 0x{:03X}  SP: {}  DT: {}  ST: {}",
+            state.index_register, state.stack_pointer, state.delay_timer, state.sound_timer
+        )?;
+
+        writeln!(f, "\nStack:")?;
+        if state.stack_pointer == 0 {
+            writeln!(f, "  (empty)")?;
+        }
+        for (i, addr) in state
+            .stack
+            .iter()
+            .take(state.stack_pointer as usize)
+            .enumerate()
+        {
+            writeln!(f, "  {i:2}: 0x{addr:03X}")?;
+        }
+
+        writeln!(f, "\nLast instructions:")?;
+        for &(clk, addr, opcode) in &self.trace.instructions {
+            writeln!(
+                f,
+                "  {clk:>10}  0x{addr:03X}  {opcode:04X}  {}",
+                disassemble(opcode)
+            )?;
+        }
+
+        writeln!(f, "\nLast input:")?;
+        if self.trace.inputs.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for (clk, event) in &self.trace.inputs {
+            let kind = match event.kind {
+                InputKind::Press => "press",
+                InputKind::Release => "release",
+            };
+            writeln!(f, "  {clk:>10}  {kind} {}", event.key)?;
+        }
+
+        write!(f, "\nScreen:\n{}", state.screen_text())
+    }
+}
@@ -1,18 +1,74 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+};
 
-use crate::{error::Chip8Error, rwlock::CheckedRead, util::run_loop};
+use crate::{
+    audio::AudioEvent,
+    cancel::CancellationToken,
+    error::Chip8Error,
+    rwlock::{CheckedRead, CheckedWrite},
+    util::run_loop,
+};
 
 pub trait AudioDriver: Send {
     fn frequency(&self) -> u64;
 
-    fn beep(&mut self) -> Result<(), Chip8Error>;
+    /// Called when the sound timer becomes non-zero at cycle `clk`.
+    fn sound_on(&mut self, clk: u64) -> Result<(), Chip8Error>;
 
-    fn run(&mut self, status: Arc<RwLock<Result<(), Chip8Error>>>, sound_timer: Arc<RwLock<u8>>) {
-        run_loop(status.clone(), self.frequency(), move |_| {
-            if *sound_timer.checked_read()? > 0 {
-                self.beep()?;
-            }
-            Ok(())
-        });
+    /// Called when the sound timer reaches zero at cycle `clk`.
+    fn sound_off(&mut self, clk: u64) -> Result<(), Chip8Error>;
+
+    /// Called once after the machine stops, with the final cycle count.
+    fn finish(&mut self, _clk: u64) -> Result<(), Chip8Error> {
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        token: CancellationToken,
+        queue: Arc<RwLock<VecDeque<(u64, AudioEvent)>>>,
+        clk: Arc<RwLock<u64>>,
+    ) {
+        run_loop(&token, self.frequency(), |_| dispatch(self, &queue));
+
+        // Events emitted in the last cycles before the machine stopped
+        let res = dispatch(self, &queue).and_then(|_| self.finish(*clk.checked_read()?));
+        if let Err(err) = res {
+            token.cancel(err);
+        }
+    }
+}
+
+fn dispatch<A: AudioDriver + ?Sized>(
+    driver: &mut A,
+    queue: &RwLock<VecDeque<(u64, AudioEvent)>>,
+) -> Result<(), Chip8Error> {
+    let events = (*queue.checked_write()?).drain(..).collect::<Vec<_>>();
+    for (clk, event) in events {
+        match event {
+            AudioEvent::SoundOn => driver.sound_on(clk)?,
+            AudioEvent::SoundOff => driver.sound_off(clk)?,
+        }
+    }
+    Ok(())
+}
+
+impl<A: AudioDriver + ?Sized> AudioDriver for Box<A> {
+    fn frequency(&self) -> u64 {
+        (**self).frequency()
+    }
+
+    fn sound_on(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        (**self).sound_on(clk)
+    }
+
+    fn sound_off(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        (**self).sound_off(clk)
+    }
+
+    fn finish(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        (**self).finish(clk)
     }
 }
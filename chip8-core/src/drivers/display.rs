@@ -1,36 +1,73 @@
 use std::sync::{Arc, RwLock};
 
 use crate::{
+    cancel::CancellationToken,
     constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    control::Control,
     error::Chip8Error,
     rwlock::CheckedRead,
+    state::StateSnapshot,
     util::run_loop,
 };
 
+/// Measurements taken by [`DisplayDriver::run`] between two frames.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    /// Measured CPU frequency
+    pub cpu_freq: u64,
+    /// Frequency the CPU is trying to run at
+    pub target_freq: u64,
+    /// Measured display refresh rate
+    pub fps: f64,
+    pub instructions: u64,
+    pub draws: u64,
+}
+
+/// Everything a display driver may show for one frame.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayFrame {
+    pub frame_buffer: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    pub state: StateSnapshot,
+    pub stats: FrameStats,
+    pub control: Control,
+}
+
 pub trait DisplayDriver: Send {
     fn frequency(&self) -> u64;
 
-    fn draw(
-        &mut self,
-        frame_buffer: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-        cpu_freq: Option<u64>,
-    ) -> Result<(), Chip8Error>;
+    fn draw(&mut self, frame: &DisplayFrame) -> Result<(), Chip8Error>;
 
     fn run(
         &mut self,
-        status: Arc<RwLock<Result<(), Chip8Error>>>,
+        token: CancellationToken,
         frame_buffer: Arc<RwLock<[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]>>,
-        clk: Arc<RwLock<u64>>,
+        snapshot: Arc<RwLock<StateSnapshot>>,
+        control: Arc<RwLock<Control>>,
     ) {
-        let mut prev_clk = 0;
-        run_loop(status.clone(), self.frequency(), move |elapsed| {
+        let mut prev = StateSnapshot::default();
+        run_loop(&token, self.frequency(), move |elapsed| {
+            let state = *snapshot.checked_read()?;
+            let control = *control.checked_read()?;
+
             // TODO: Put behind feature flag
-            let curr_clk = *clk.checked_read()?;
-            let freq = (curr_clk - prev_clk) as f64 / elapsed.as_secs_f64();
-            let freq = freq.round() as u64;
+            // Loading an earlier state moves the clk back
+            let instructions = state.clk.saturating_sub(prev.clk);
+            let stats = FrameStats {
+                cpu_freq: (instructions as f64 / elapsed.as_secs_f64()).round() as u64,
+                target_freq: control.frequency(),
+                fps: 1.0 / elapsed.as_secs_f64(),
+                instructions,
+                draws: state.draws.saturating_sub(prev.draws),
+            };
 
-            self.draw(*frame_buffer.checked_read()?, Some(freq))?;
-            prev_clk = curr_clk;
+            let frame = DisplayFrame {
+                frame_buffer: *frame_buffer.checked_read()?,
+                state,
+                stats,
+                control,
+            };
+            self.draw(&frame)?;
+            prev = state;
 
             Ok(())
         });
@@ -1,11 +1,11 @@
-use std::{
-    collections::VecDeque,
-    sync::{Arc, RwLock},
-};
+use std::sync::{Arc, RwLock};
 
 use crate::{
+    cancel::CancellationToken,
+    control::Control,
     error::Chip8Error,
-    input::{InputEvent, InputQueue},
+    input::{HostEvent, InputEvent, InputQueue, InputSchedule},
+    movie::Checkpoints,
     rwlock::{CheckedRead, CheckedWrite},
     util::run_loop,
 };
@@ -13,27 +13,117 @@ use crate::{
 pub trait InputDriver: Send {
     fn frequency(&self) -> u64;
 
-    fn poll(&mut self) -> Result<Option<InputEvent>, Chip8Error>;
+    fn poll(&mut self) -> Result<Option<HostEvent>, Chip8Error>;
 
     fn log_input(&mut self, _clk: u64, _input: InputEvent) -> Result<(), Chip8Error> {
         Ok(())
     }
 
+    fn log_reset(&mut self, _clk: u64) -> Result<(), Chip8Error> {
+        Ok(())
+    }
+
+    fn log_checkpoint(&mut self, _clk: u64, _checksum: u64) -> Result<(), Chip8Error> {
+        Ok(())
+    }
+
+    /// Called when the user takes over a replay at `clk`. Recorded events after
+    /// it are discarded.
+    fn take_over(&mut self, _clk: u64) -> Result<(), Chip8Error> {
+        Ok(())
+    }
+
+    /// Events generated by the driver itself (autofire, macros) that are due at
+    /// `clk`. They are queued and logged like any other input.
+    fn scheduled(&mut self, _clk: u64) -> Result<Vec<InputEvent>, Chip8Error> {
+        Ok(vec![])
+    }
+
+    /// Whether [`Self::scheduled`] may return events at later cycles, like
+    /// autofire while its key is held. The CPU then keeps the clk running
+    /// rather than parking while it waits for a key.
+    fn is_scheduling(&self) -> bool {
+        false
+    }
+
+    /// Polls until `token` is cancelled, waking up the CPU whenever there is
+    /// something new for it.
     fn run(
         &mut self,
-        status: Arc<RwLock<Result<(), Chip8Error>>>,
-        queue: Arc<RwLock<VecDeque<(u64, InputEvent)>>>,
+        token: CancellationToken,
+        queue: Arc<RwLock<InputSchedule>>,
         clk: Arc<RwLock<u64>>,
+        control: Arc<RwLock<Control>>,
+        checkpoints: Arc<RwLock<Checkpoints>>,
     ) {
-        run_loop(status.clone(), self.frequency(), move |_| {
-            let maybe_event = self.poll()?;
-
-            let clk = *clk.checked_read()?;
-            let queue_clk = (*queue.checked_read()?).back_clk();
-            if clk >= queue_clk.unwrap_or_default() {
-                if let Some(event) = maybe_event {
-                    self.log_input(clk, event)?;
-                    (*queue.checked_write()?).enqueue(clk, event);
+        let waker = token.clone();
+        run_loop(&token, self.frequency(), move |_| {
+            let mut events = vec![];
+            let mut reset = false;
+            let mut take_over = false;
+            let mut wake = false;
+            match self.poll()? {
+                // Control events are applied even while replaying
+                Some(HostEvent::Control(event)) => {
+                    (*control.checked_write()?).apply(event);
+                    wake = true;
+                }
+                Some(HostEvent::Key(event)) => events.push(event),
+                Some(HostEvent::Reset) => reset = true,
+                Some(HostEvent::TakeOver) => take_over = true,
+                None => {}
+            }
+
+            // The CPU can't finish the current cycle while the clk is read, so
+            // the events are queued for exactly the next one. Logging them can
+            // wait until the CPU is free to go on.
+            let guard = clk.checked_read()?;
+            let clk = *guard;
+            let next_clk = clk + 1;
+            if take_over {
+                // Events at the current clk may already have been applied
+                (*queue.checked_write()?).truncate_after(clk);
+            }
+            events.extend(self.scheduled(next_clk)?);
+            let replaying = (*queue.checked_read()?).back_clk() > Some(next_clk);
+            if !replaying && (reset || !events.is_empty()) {
+                let mut queue = queue.checked_write()?;
+                // Before the events, as the CPU applies them in that order
+                if reset {
+                    queue.enqueue_reset(next_clk);
+                }
+                for &event in &events {
+                    queue.enqueue(next_clk, event);
+                }
+                wake = true;
+            }
+            drop(guard);
+
+            if take_over {
+                self.take_over(clk)?;
+            }
+            if !replaying {
+                if reset {
+                    self.log_reset(next_clk)?;
+                }
+                for event in events {
+                    self.log_input(next_clk, event)?;
+                }
+            }
+
+            let scheduling = self.is_scheduling();
+            if scheduling != control.checked_read()?.scheduled_input {
+                control.checked_write()?.scheduled_input = scheduling;
+                wake = true;
+            }
+            if wake {
+                waker.notify();
+            }
+
+            let pending = std::mem::take(&mut checkpoints.checked_write()?.pending);
+            if !replaying {
+                for (clk, checksum) in pending {
+                    self.log_checkpoint(clk, checksum)?;
                 }
             }
             Ok(())
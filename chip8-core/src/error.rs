@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::state::Address;
+use crate::{control::StopReason, state::Address};
 
 #[derive(Error, Debug, Clone)]
 pub enum Chip8Error {
@@ -18,10 +18,18 @@ pub enum Chip8Error {
     AudioError(String),
     #[error("Async/Await Error: {0}")]
     AsyncAwaitError(String),
+    #[error("Shutdown timed out: {0}")]
+    ShutdownTimeout(String),
     #[error("Mutex read error: {0}")]
     MutexReadError(String),
     #[error("Mutex write error: {0}")]
     MutexWriteError(String),
+    #[error("Movie Error: {0}")]
+    MovieError(String),
+    #[error("Script Error: {0}")]
+    ScriptError(String),
     #[error("Interrupted")]
     Interrupt,
+    #[error("Stopped: {0}")]
+    Stopped(StopReason),
 }
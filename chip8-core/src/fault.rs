@@ -0,0 +1,236 @@
+//! What to do when an instruction fails, for ROMs that rely on a few
+//! unsupported opcodes and would otherwise end the run.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::{error::Chip8Error, state::Address};
+
+/// Errors an instruction can fail with that a [`FaultPolicy`] may let through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    UnimplementedOpcode,
+    MemoryAccessOutOfBounds,
+}
+
+impl FaultKind {
+    /// The kind of `error`, if it is one that a policy applies to.
+    pub fn of(error: &Chip8Error) -> Option<Self> {
+        match error {
+            Chip8Error::UnimplementedOpcode(_) => Some(Self::UnimplementedOpcode),
+            Chip8Error::MemoryAccessOutOfBounds(_) => Some(Self::MemoryAccessOutOfBounds),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FaultKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnimplementedOpcode => write!(f, "unimplemented-opcode"),
+            Self::MemoryAccessOutOfBounds => write!(f, "memory-out-of-bounds"),
+        }
+    }
+}
+
+impl FromStr for FaultKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unimplemented-opcode" | "opcode" => Ok(Self::UnimplementedOpcode),
+            "memory-out-of-bounds" | "memory" => Ok(Self::MemoryAccessOutOfBounds),
+            _ => Err(format!("Unknown fault kind: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FaultAction {
+    /// End the run, the default
+    #[default]
+    Abort,
+    /// Log the fault and carry on with the next instruction
+    Skip,
+    /// Carry on with the next instruction as if nothing happened. The fault
+    /// is only counted
+    Nop,
+    /// Pause on the faulting instruction, which is skipped when resuming. Runs
+    /// without a terminal to resume from skip it at once
+    Pause,
+}
+
+impl FromStr for FaultAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            "nop" => Ok(Self::Nop),
+            "pause" => Ok(Self::Pause),
+            _ => Err(format!("Unknown fault action: {s}")),
+        }
+    }
+}
+
+impl TryFrom<u8> for FaultAction {
+    type Error = Chip8Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Abort),
+            1 => Ok(Self::Skip),
+            2 => Ok(Self::Nop),
+            3 => Ok(Self::Pause),
+            _ => Err(Chip8Error::MovieError(format!(
+                "Invalid fault action {value}"
+            ))),
+        }
+    }
+}
+
+impl Display for FaultAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Abort => write!(f, "abort"),
+            Self::Skip => write!(f, "skip"),
+            Self::Nop => write!(f, "nop"),
+            Self::Pause => write!(f, "pause"),
+        }
+    }
+}
+
+/// The [`FaultAction`] for each [`FaultKind`]. Errors of no kind, like a
+/// driver failing, always end the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultPolicy {
+    pub unimplemented_opcode: FaultAction,
+    pub memory_access_out_of_bounds: FaultAction,
+}
+
+impl FaultPolicy {
+    pub fn all(action: FaultAction) -> Self {
+        Self {
+            unimplemented_opcode: action,
+            memory_access_out_of_bounds: action,
+        }
+    }
+
+    pub fn set(&mut self, kind: FaultKind, action: FaultAction) {
+        match kind {
+            FaultKind::UnimplementedOpcode => self.unimplemented_opcode = action,
+            FaultKind::MemoryAccessOutOfBounds => self.memory_access_out_of_bounds = action,
+        }
+    }
+
+    pub fn rules(&self) -> [(FaultKind, FaultAction); 2] {
+        [
+            (FaultKind::UnimplementedOpcode, self.unimplemented_opcode),
+            (
+                FaultKind::MemoryAccessOutOfBounds,
+                self.memory_access_out_of_bounds,
+            ),
+        ]
+    }
+
+    pub fn action(&self, error: &Chip8Error) -> FaultAction {
+        match FaultKind::of(error) {
+            Some(FaultKind::UnimplementedOpcode) => self.unimplemented_opcode,
+            Some(FaultKind::MemoryAccessOutOfBounds) => self.memory_access_out_of_bounds,
+            None => FaultAction::Abort,
+        }
+    }
+}
+
+/// `KIND=ACTION` for every kind, as written in movie headers.
+impl Display for FaultPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rules = self
+            .rules()
+            .map(|(kind, action)| format!("{kind}={action}"));
+        write!(f, "{}", rules.join(" "))
+    }
+}
+
+impl FromStr for FaultPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::default();
+        for rule in s.split_whitespace() {
+            let (kind, action) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid fault rule: {rule}"))?;
+            policy.set(kind.parse()?, action.parse()?);
+        }
+        Ok(policy)
+    }
+}
+
+/// A fault the run went on after.
+#[derive(Debug, Clone)]
+pub struct SuppressedFault {
+    pub clk: u64,
+    pub pc: Address,
+    pub opcode: u16,
+    pub error: Chip8Error,
+    pub action: FaultAction,
+}
+
+impl Display for SuppressedFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "clk {}: {} at PC 0x{:03X} (opcode 0x{:04X}), {}",
+            self.clk, self.error, self.pc, self.opcode, self.action
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rules_by_fault_kind() {
+        let mut policy = FaultPolicy::all(FaultAction::Skip);
+        policy.set(FaultKind::MemoryAccessOutOfBounds, FaultAction::Pause);
+
+        let opcode = Chip8Error::UnimplementedOpcode(0);
+        let memory = Chip8Error::MemoryAccessOutOfBounds(0x1000);
+        assert_eq!(policy.action(&opcode), FaultAction::Skip);
+        assert_eq!(policy.action(&memory), FaultAction::Pause);
+        // Not a fault of the program, so never let through
+        assert_eq!(
+            policy.action(&Chip8Error::AudioError(String::new())),
+            FaultAction::Abort
+        );
+        assert_eq!(FaultPolicy::default().action(&opcode), FaultAction::Abort);
+    }
+
+    #[test]
+    fn parses_what_it_prints() {
+        for action in ["abort", "skip", "nop", "pause"] {
+            let action: FaultAction = action.parse().unwrap();
+            assert_eq!(FaultAction::try_from(action as u8).ok(), Some(action));
+            let policy = FaultPolicy {
+                memory_access_out_of_bounds: action,
+                ..FaultPolicy::default()
+            };
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+
+        // Later rules win, short kind names included
+        assert_eq!(
+            "opcode=nop memory=skip opcode=pause".parse(),
+            Ok(FaultPolicy {
+                unimplemented_opcode: FaultAction::Pause,
+                memory_access_out_of_bounds: FaultAction::Skip,
+            })
+        );
+        assert!("opcode".parse::<FaultPolicy>().is_err());
+        assert!("stack=skip".parse::<FaultPolicy>().is_err());
+        assert!("opcode=retry".parse::<FaultPolicy>().is_err());
+        assert!(FaultAction::try_from(4).is_err());
+    }
+}
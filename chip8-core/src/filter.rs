@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+use crate::constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+pub const MAX_INTENSITY: u8 = u8::MAX;
+
+/// Post-processing applied to consecutive frames to hide the flicker caused by
+/// sprites being erased and redrawn with XOR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterMode {
+    /// Frames are shown as they are
+    #[default]
+    None,
+    /// A pixel is lit if it was lit in any of the last N frames
+    Blend(usize),
+    /// A pixel fades out over N frames after turning off
+    Phosphor(usize),
+}
+
+/// Turns frame buffers into per-pixel intensities, from 0 (off) to
+/// [`MAX_INTENSITY`] (fully lit), according to a [`FilterMode`].
+///
+/// Every call to [`FrameFilter::apply`] is one frame, so drivers should call it
+/// once per drawn frame.
+pub struct FrameFilter {
+    mode: FilterMode,
+    history: VecDeque<[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]>,
+    intensity: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+}
+
+impl FrameFilter {
+    pub fn new(mode: FilterMode) -> Self {
+        Self {
+            mode,
+            history: VecDeque::new(),
+            intensity: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+        }
+    }
+
+    pub fn apply(
+        &mut self,
+        frame_buffer: &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    ) -> [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT] {
+        match self.mode {
+            FilterMode::None => {
+                self.intensity = frame_buffer.map(|row| row.map(lit));
+            }
+            FilterMode::Blend(frames) => {
+                self.history.push_back(*frame_buffer);
+                while self.history.len() > frames.max(1) {
+                    self.history.pop_front();
+                }
+
+                for (y, row) in self.intensity.iter_mut().enumerate() {
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        *pixel = lit(self.history.iter().any(|frame| frame[y][x]));
+                    }
+                }
+            }
+            FilterMode::Phosphor(frames) => {
+                let step = MAX_INTENSITY.div_ceil(frames.clamp(1, MAX_INTENSITY as usize) as u8);
+
+                for (row, fb_row) in self.intensity.iter_mut().zip(frame_buffer) {
+                    for (pixel, &on) in row.iter_mut().zip(fb_row) {
+                        *pixel = if on {
+                            MAX_INTENSITY
+                        } else {
+                            pixel.saturating_sub(step)
+                        };
+                    }
+                }
+            }
+        }
+
+        self.intensity
+    }
+}
+
+fn lit(on: bool) -> u8 {
+    if on {
+        MAX_INTENSITY
+    } else {
+        0
+    }
+}
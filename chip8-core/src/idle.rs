@@ -0,0 +1,323 @@
+//! Detection of programs that stopped making progress on their own: ROMs that
+//! end in a jump to itself, or spin waiting for a key or the delay timer.
+//!
+//! Every backward jump (or instruction run again, like `FX0A`) ends an
+//! iteration of a loop. When two iterations in a row start at the same address
+//! with the same registers, and nothing was drawn or written in between, the
+//! program can only get out of the loop through the keypad or the delay timer.
+
+use std::fmt::Display;
+
+use crate::{
+    constants::NUM_REGISTERS,
+    instruction::Instruction,
+    state::{Address, State, Word},
+};
+
+/// What the program is doing, as far as the CPU loop can tell.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Activity {
+    #[default]
+    Running,
+    /// Jumping to itself, or looping in a way that only a reset can end
+    Halted,
+    /// Waiting with `FX0A`, or polling the keypad in a loop
+    WaitingForKey,
+    /// Looping until the delay timer runs out
+    WaitingForTimer,
+}
+
+impl Display for Activity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Running => write!(f, "running"),
+            Self::Halted => write!(f, "halted"),
+            Self::WaitingForKey => write!(f, "waiting for a key"),
+            Self::WaitingForTimer => write!(f, "waiting for the delay timer"),
+        }
+    }
+}
+
+// Machine state at the start of a loop iteration
+#[derive(Debug, Clone, Copy)]
+struct LoopHead {
+    pc: Address,
+    /// Address of the instruction that jumped back
+    tail: Address,
+    registers: [Word; NUM_REGISTERS],
+    index_register: Address,
+    stack_pointer: Word,
+}
+
+impl LoopHead {
+    fn new(pc: Address, tail: Address, state: &impl State) -> Self {
+        Self {
+            pc,
+            tail,
+            registers: std::array::from_fn(|i| state.register(i as Word)),
+            index_register: state.index_register(),
+            stack_pointer: state.stack_pointer(),
+        }
+    }
+
+    // Registers in `ignored` may differ, as a bit mask
+    fn same_as(&self, other: &Self, ignored: u16) -> bool {
+        self.pc == other.pc
+            && self.index_register == other.index_register
+            && self.stack_pointer == other.stack_pointer
+            && (0..NUM_REGISTERS)
+                .filter(|i| ignored & (1 << i) == 0)
+                .all(|i| self.registers[i] == other.registers[i])
+    }
+
+    // Whether `pc` at `stack_pointer` is outside of the loop
+    fn left(&self, pc: Address, stack_pointer: Word) -> bool {
+        stack_pointer < self.stack_pointer
+            || (stack_pointer == self.stack_pointer && !(self.pc..=self.tail).contains(&pc))
+    }
+}
+
+/// Follows the executed instructions to tell what the program is doing.
+#[derive(Debug, Default)]
+pub struct IdleDetector {
+    head: Option<LoopHead>,
+    activity: Activity,
+    // What happened since the loop head
+    changed: bool,
+    reads_keys: bool,
+    reads_delay: bool,
+    /// Registers loaded from the delay timer, which change while waiting
+    from_delay: u16,
+}
+
+impl IdleDetector {
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+
+    /// Accounts for `instruction`, which was just executed at `pc`.
+    pub fn observe(&mut self, pc: Address, instruction: &Instruction, state: &impl State) {
+        match instruction {
+            Instruction::ClearDisplay
+            | Instruction::Draw(..)
+            | Instruction::StoreBCD(_)
+            | Instruction::StoreRegisters(_)
+            | Instruction::Random(..)
+            | Instruction::SetDelay(_)
+            | Instruction::SetSound(_) => self.changed = true,
+            Instruction::SkipKeyPressed(_)
+            | Instruction::SkipKeyNotPressed(_)
+            | Instruction::WaitKeyPress(_) => self.reads_keys = true,
+            Instruction::LoadDelay(x) => {
+                self.reads_delay = true;
+                self.from_delay |= 1 << x;
+            }
+            _ => {}
+        }
+
+        let next = state.program_counter();
+        if next > pc {
+            let left = self
+                .head
+                .is_some_and(|head| head.left(next, state.stack_pointer()));
+            if self.changed || left {
+                self.activity = Activity::Running;
+            }
+            return;
+        }
+
+        // A loop iteration ends
+        let head = LoopHead::new(next, pc, state);
+        let repeated = self
+            .head
+            .is_some_and(|prev| prev.same_as(&head, self.from_delay));
+        self.activity = if self.changed || !repeated {
+            Activity::Running
+        } else if self.reads_keys {
+            Activity::WaitingForKey
+        } else if self.reads_delay && (state.delay_timer() > 0 || self.stale_delay(state)) {
+            Activity::WaitingForTimer
+        } else {
+            Activity::Halted
+        };
+
+        self.head = Some(head);
+        self.changed = false;
+        self.reads_keys = false;
+        self.reads_delay = false;
+        self.from_delay = 0;
+    }
+
+    // Whether registers were loaded before the delay timer ran out, so that
+    // the loop has yet to see it at 0
+    fn stale_delay(&self, state: &impl State) -> bool {
+        (0..NUM_REGISTERS as Word)
+            .filter(|i| self.from_delay & (1 << i) != 0)
+            .any(|i| state.register(i) != state.delay_timer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SimpleState;
+
+    // Executes `instruction` at `pc` as far as the detector can tell, which
+    // is only by where it went
+    fn run(
+        detector: &mut IdleDetector,
+        state: &mut SimpleState,
+        pc: Address,
+        instruction: Instruction,
+        next: Address,
+    ) -> Activity {
+        state.set_program_counter(next);
+        detector.observe(pc, &instruction, state);
+        detector.activity()
+    }
+
+    #[test]
+    fn halts_on_a_jump_to_itself() {
+        let (mut detector, mut state) = (IdleDetector::default(), SimpleState::default());
+        let jump = Instruction::Jump(0x200);
+        assert_eq!(
+            run(&mut detector, &mut state, 0x200, jump, 0x200),
+            Activity::Running
+        );
+        assert_eq!(
+            run(&mut detector, &mut state, 0x200, jump, 0x200),
+            Activity::Halted
+        );
+    }
+
+    #[test]
+    fn waits_for_a_key() {
+        let (mut detector, mut state) = (IdleDetector::default(), SimpleState::default());
+        for _ in 0..2 {
+            run(
+                &mut detector,
+                &mut state,
+                0x200,
+                Instruction::SkipKeyPressed(0),
+                0x202,
+            );
+            run(
+                &mut detector,
+                &mut state,
+                0x202,
+                Instruction::Jump(0x200),
+                0x200,
+            );
+        }
+        assert_eq!(detector.activity(), Activity::WaitingForKey);
+
+        // Pressed, out of the loop
+        run(
+            &mut detector,
+            &mut state,
+            0x200,
+            Instruction::SkipKeyPressed(0),
+            0x204,
+        );
+        assert_eq!(detector.activity(), Activity::Running);
+
+        let wait = Instruction::WaitKeyPress(1);
+        run(&mut detector, &mut state, 0x300, wait, 0x300);
+        assert_eq!(
+            run(&mut detector, &mut state, 0x300, wait, 0x300),
+            Activity::WaitingForKey
+        );
+    }
+
+    #[test]
+    fn waits_for_the_delay_timer() {
+        let (mut detector, mut state) = (IdleDetector::default(), SimpleState::default());
+        for delay in [3, 2] {
+            state.set_delay_timer(delay);
+            state.set_register(0, delay);
+            run(
+                &mut detector,
+                &mut state,
+                0x200,
+                Instruction::LoadDelay(0),
+                0x202,
+            );
+            run(
+                &mut detector,
+                &mut state,
+                0x202,
+                Instruction::SkipEqual(0, 0),
+                0x204,
+            );
+            run(
+                &mut detector,
+                &mut state,
+                0x204,
+                Instruction::Jump(0x200),
+                0x200,
+            );
+        }
+        assert_eq!(detector.activity(), Activity::WaitingForTimer);
+
+        // Ran out, and the next iteration skips out of the loop
+        state.set_delay_timer(0);
+        state.set_register(0, 0);
+        run(
+            &mut detector,
+            &mut state,
+            0x200,
+            Instruction::LoadDelay(0),
+            0x202,
+        );
+        run(
+            &mut detector,
+            &mut state,
+            0x202,
+            Instruction::SkipEqual(0, 0),
+            0x206,
+        );
+        assert_eq!(detector.activity(), Activity::Running);
+    }
+
+    #[test]
+    fn keeps_running_while_the_loop_changes_something() {
+        let (mut detector, mut state) = (IdleDetector::default(), SimpleState::default());
+        for _ in 0..3 {
+            run(
+                &mut detector,
+                &mut state,
+                0x200,
+                Instruction::Draw(0, 1, 5),
+                0x202,
+            );
+            run(
+                &mut detector,
+                &mut state,
+                0x202,
+                Instruction::Jump(0x200),
+                0x200,
+            );
+        }
+        assert_eq!(detector.activity(), Activity::Running);
+
+        // A counter never repeats the registers
+        for i in 1..=3 {
+            state.set_register(2, i);
+            run(
+                &mut detector,
+                &mut state,
+                0x300,
+                Instruction::Add(2, 1),
+                0x302,
+            );
+            run(
+                &mut detector,
+                &mut state,
+                0x302,
+                Instruction::Jump(0x300),
+                0x300,
+            );
+        }
+        assert_eq!(detector.activity(), Activity::Running);
+    }
+}
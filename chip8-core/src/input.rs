@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use crate::{error::Chip8Error, keypad::Key};
+use crate::{control::ControlEvent, error::Chip8Error, keypad::Key};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InputKind {
@@ -26,10 +26,25 @@ pub struct InputEvent {
     pub kind: InputKind,
 }
 
+/// Anything an input driver can produce: keypad input for the CHIP-8, or a
+/// request to the emulator itself.
+#[derive(Debug, Clone, Copy)]
+pub enum HostEvent {
+    Key(InputEvent),
+    Control(ControlEvent),
+    /// Reload the ROM into a fresh machine. Unlike the control events it
+    /// changes what the program does, so it is recorded like key input
+    Reset,
+    /// Stop replaying and keep the input from now on instead
+    TakeOver,
+}
+
 pub trait InputQueue {
     fn back_clk(&self) -> Option<u64>;
     fn enqueue(&mut self, clk: u64, event: InputEvent);
     fn dequeue(&mut self, current_clk: u64) -> Option<InputEvent>;
+    /// Drops the events queued after `clk`.
+    fn truncate_after(&mut self, clk: u64);
 }
 
 impl InputQueue for VecDeque<(u64, InputEvent)> {
@@ -53,4 +68,61 @@ impl InputQueue for VecDeque<(u64, InputEvent)> {
             None
         }
     }
+
+    fn truncate_after(&mut self, clk: u64) {
+        self.retain(|(event_clk, _)| *event_clk <= clk);
+    }
+}
+
+/// Input waiting for the CPU, each keyed by the clk it applies at.
+#[derive(Debug, Default)]
+pub struct InputSchedule {
+    events: VecDeque<(u64, InputEvent)>,
+    resets: VecDeque<u64>,
+}
+
+impl InputSchedule {
+    pub fn new(events: Vec<(u64, InputEvent)>, resets: Vec<u64>) -> Self {
+        Self {
+            events: VecDeque::from(events),
+            resets: VecDeque::from(resets),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.resets.is_empty()
+    }
+
+    pub fn enqueue_reset(&mut self, clk: u64) {
+        self.resets.push_back(clk);
+    }
+
+    /// Takes the reset due by `current_clk`, if there is one.
+    pub fn dequeue_reset(&mut self, current_clk: u64) -> bool {
+        if self.resets.front().is_some_and(|clk| *clk <= current_clk) {
+            self.resets.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl InputQueue for InputSchedule {
+    fn back_clk(&self) -> Option<u64> {
+        self.events.back_clk().max(self.resets.back().copied())
+    }
+
+    fn enqueue(&mut self, clk: u64, event: InputEvent) {
+        self.events.enqueue(clk, event);
+    }
+
+    fn dequeue(&mut self, current_clk: u64) -> Option<InputEvent> {
+        self.events.dequeue(current_clk)
+    }
+
+    fn truncate_after(&mut self, clk: u64) {
+        self.events.truncate_after(clk);
+        self.resets.retain(|reset_clk| *reset_clk <= clk);
+    }
 }
@@ -1,9 +1,14 @@
-use crate::state::{Address, Word};
+use std::fmt::Display;
+
+use crate::{
+    error::Chip8Error,
+    state::{Address, Word},
+};
 
 type Nibble = u8; // ideally u4
 type RegisterIndex = u8; // ideally u4
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     ClearDisplay,
     Return,
@@ -44,3 +49,156 @@ pub enum Instruction {
     StoreRegisters(RegisterIndex),
     LoadMemory(RegisterIndex),
 }
+
+impl TryFrom<u16> for Instruction {
+    type Error = Chip8Error;
+
+    fn try_from(opcode: u16) -> Result<Self, Self::Error> {
+        let x = ((opcode >> 8) & 0x000F) as u8;
+        let y = ((opcode >> 4) & 0x000F) as u8;
+
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode & 0xF0FF {
+                // 0x00E0
+                0x00E0 => Ok(Self::ClearDisplay),
+                // 0x00EE
+                0x00EE => Ok(Self::Return),
+                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+            },
+            // 0x1NNN
+            0x1000 => Ok(Self::Jump(nnn)),
+            // 0x2NNN
+            0x2000 => Ok(Self::Call(nnn)),
+            // 0x3XNN
+            0x3000 => Ok(Self::SkipEqual(x, nn)),
+            // 0x4XNN
+            0x4000 => Ok(Self::SkipNotEqual(x, nn)),
+            // 0x5XY0
+            0x5000 => match opcode & 0xF00F {
+                0x5000 => Ok(Self::SkipEqualXY(x, y)),
+                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+            },
+            // 0x6XNN
+            0x6000 => Ok(Self::Load(x, nn)),
+            // 0x7XNN
+            0x7000 => Ok(Self::Add(x, nn)),
+            0x8000 => match opcode & 0xF00F {
+                // 0x8XY0
+                0x8000 => Ok(Self::Move(x, y)),
+                // 0x8XY1
+                0x8001 => Ok(Self::Or(x, y)),
+                // 0x8XY2
+                0x8002 => Ok(Self::And(x, y)),
+                // 0x8XY3
+                0x8003 => Ok(Self::Xor(x, y)),
+                // 0x8XY4
+                0x8004 => Ok(Self::AddXY(x, y)),
+                // 0x8XY5
+                0x8005 => Ok(Self::SubXY(x, y)),
+                // 0x8XY6
+                0x8006 => Ok(Self::ShiftRight(x)),
+                // 0x8XY7
+                0x8007 => Ok(Self::SubYX(x, y)),
+                // 0x8XYE
+                0x800E => Ok(Self::ShiftLeft(x)),
+                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+            },
+            0x9000 => match opcode & 0xF00F {
+                // 0x9XY0
+                0x9000 => Ok(Self::SkipNotEqualXY(x, y)),
+                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+            },
+            // 0xANNN
+            0xA000 => Ok(Self::LoadI(nnn)),
+            // 0xBNNN
+            0xB000 => Ok(Self::JumpV0(nnn)),
+            // 0xCXNN
+            0xC000 => Ok(Self::Random(x, nn)),
+            // 0xDXYN
+            0xD000 => Ok(Self::Draw(x, y, n)),
+            0xE000 => match opcode & 0xF0FF {
+                // 0xEX9E
+                0xE09E => Ok(Self::SkipKeyPressed(x)),
+                // 0xEXA1
+                0xE0A1 => Ok(Self::SkipKeyNotPressed(x)),
+                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+            },
+            0xF000 => match opcode & 0xF0FF {
+                // 0xFX07
+                0xF007 => Ok(Self::LoadDelay(x)),
+                // 0xFX0A
+                0xF00A => Ok(Self::WaitKeyPress(x)),
+                // 0xFX15
+                0xF015 => Ok(Self::SetDelay(x)),
+                // 0xFX18
+                0xF018 => Ok(Self::SetSound(x)),
+                // 0xFX1E
+                0xF01E => Ok(Self::AddI(x)),
+                // 0xFX29
+                0xF029 => Ok(Self::LoadFont(x)),
+                // 0xFX33
+                0xF033 => Ok(Self::StoreBCD(x)),
+                // 0xFX55
+                0xF055 => Ok(Self::StoreRegisters(x)),
+                // 0xFX65
+                0xF065 => Ok(Self::LoadMemory(x)),
+                _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+            },
+            _ => Err(Chip8Error::UnimplementedOpcode(opcode)),
+        }
+    }
+}
+
+// Mnemonics from Cowgod's Chip-8 technical reference
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::ClearDisplay => write!(f, "CLS"),
+            Self::Return => write!(f, "RET"),
+            Self::Jump(nnn) => write!(f, "JP 0x{nnn:03X}"),
+            Self::Call(nnn) => write!(f, "CALL 0x{nnn:03X}"),
+            Self::SkipEqual(x, nn) => write!(f, "SE V{x:X}, 0x{nn:02X}"),
+            Self::SkipNotEqual(x, nn) => write!(f, "SNE V{x:X}, 0x{nn:02X}"),
+            Self::SkipEqualXY(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Self::Load(x, nn) => write!(f, "LD V{x:X}, 0x{nn:02X}"),
+            Self::Add(x, nn) => write!(f, "ADD V{x:X}, 0x{nn:02X}"),
+            Self::Move(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Self::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Self::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Self::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Self::AddXY(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Self::SubXY(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Self::ShiftRight(x) => write!(f, "SHR V{x:X}"),
+            Self::SubYX(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Self::ShiftLeft(x) => write!(f, "SHL V{x:X}"),
+            Self::SkipNotEqualXY(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Self::LoadI(nnn) => write!(f, "LD I, 0x{nnn:03X}"),
+            Self::JumpV0(nnn) => write!(f, "JP V0, 0x{nnn:03X}"),
+            Self::Random(x, nn) => write!(f, "RND V{x:X}, 0x{nn:02X}"),
+            Self::Draw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Self::SkipKeyPressed(x) => write!(f, "SKP V{x:X}"),
+            Self::SkipKeyNotPressed(x) => write!(f, "SKNP V{x:X}"),
+            Self::LoadDelay(x) => write!(f, "LD V{x:X}, DT"),
+            Self::WaitKeyPress(x) => write!(f, "LD V{x:X}, K"),
+            Self::SetDelay(x) => write!(f, "LD DT, V{x:X}"),
+            Self::SetSound(x) => write!(f, "LD ST, V{x:X}"),
+            Self::AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Self::LoadFont(x) => write!(f, "LD F, V{x:X}"),
+            Self::StoreBCD(x) => write!(f, "LD B, V{x:X}"),
+            Self::StoreRegisters(x) => write!(f, "LD [I], V{x:X}"),
+            Self::LoadMemory(x) => write!(f, "LD V{x:X}, [I]"),
+        }
+    }
+}
+
+/// Disassembles `opcode`, or gives it as data if it is not an instruction.
+pub fn disassemble(opcode: u16) -> String {
+    match Instruction::try_from(opcode) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => format!("DW 0x{opcode:04X}"),
+    }
+}
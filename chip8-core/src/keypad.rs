@@ -31,6 +31,14 @@ pub enum Key {
     KeyF,
 }
 
+/// Physical arrangement of the keys on the COSMAC VIP hex keypad.
+pub const KEYPAD_LAYOUT: [[Key; 4]; 4] = [
+    [Key::Key1, Key::Key2, Key::Key3, Key::KeyC],
+    [Key::Key4, Key::Key5, Key::Key6, Key::KeyD],
+    [Key::Key7, Key::Key8, Key::Key9, Key::KeyE],
+    [Key::KeyA, Key::Key0, Key::KeyB, Key::KeyF],
+];
+
 impl From<Key> for char {
     fn from(key: Key) -> Self {
         match key {
@@ -1,12 +1,22 @@
+pub mod audio;
+pub mod cancel;
 mod chip8;
 pub mod constants;
+pub mod control;
 pub mod cpu;
+pub mod crash;
 pub mod drivers;
 pub mod error;
+pub mod fault;
+pub mod filter;
+pub mod idle;
 pub mod input;
 pub mod instruction;
 pub mod keypad;
+pub mod movie;
 pub mod rwlock;
+pub mod scenario;
+pub mod script;
 pub mod state;
 pub mod util;
 
@@ -0,0 +1,1057 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::{
+    constants::TICKS_PER_TIMER,
+    error::Chip8Error,
+    fault::FaultPolicy,
+    input::{InputEvent, InputKind},
+    keypad::Key,
+    state::SaveState,
+};
+
+/// Version of both encodings, bumped on incompatible changes.
+pub const MOVIE_VERSION: u32 = 1;
+/// The only platform emulated so far.
+pub const PLATFORM: &str = "chip-8";
+pub const EMULATOR: &str = concat!("chip8-core ", env!("CARGO_PKG_VERSION"));
+
+const TEXT_MAGIC: &str = "chip8-movie";
+const BINARY_MAGIC: &[u8; 4] = b"C8MV";
+const EVENTS_MARKER: &str = "events";
+const CHECKPOINT: &str = "checkpoint";
+const RESET: &str = "reset";
+// Take the place of the key byte in the binary encoding
+const CHECKPOINT_TAG: u8 = 0x80;
+const RESET_TAG: u8 = 0x81;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieEncoding {
+    /// One `key value` line per header field, then one `clk key press|release`
+    /// line per event and one `reset clk` line per reset
+    Text,
+    /// Length-prefixed header fields, then events, resets and checkpoints as LEB128
+    /// deltas from the clk of the previous record of the same kind
+    Binary,
+}
+
+impl MovieEncoding {
+    /// Guesses the encoding of `bytes` from their first bytes.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(BINARY_MAGIC) {
+            Some(Self::Binary)
+        } else if bytes.starts_with(TEXT_MAGIC.as_bytes()) {
+            Some(Self::Text)
+        } else {
+            None
+        }
+    }
+}
+
+/// Unit of the event timestamps in a movie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timebase {
+    /// CPU cycles. Exact, but only replays at the same ticks per timer
+    #[default]
+    Cycles,
+    /// Timer ticks (frames) since the start of the movie, replayable at any
+    /// ticks per timer. Checkpoints are not kept, as they are taken by cycle
+    Frames,
+}
+
+impl Timebase {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Cycles => "cycles",
+            Self::Frames => "frames",
+        }
+    }
+}
+
+/// What a replay needs to know besides the events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieHeader {
+    /// Lowercase hex SHA-1 of the ROM
+    pub rom_hash: String,
+    pub seed: u64,
+    pub clock_frequency: u64,
+    /// Cycles per timer tick when the movie was recorded
+    pub ticks_per_timer: u64,
+    pub timebase: Timebase,
+    /// Faults the run went on after, which a replay must go on after too
+    pub fault_policy: FaultPolicy,
+    /// Position of the seeded random number generator at the start state
+    pub rng_position: u64,
+    pub platform: String,
+    pub emulator: String,
+    pub author: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl MovieHeader {
+    pub fn new(rom_hash: String, seed: u64, clock_frequency: u64) -> Self {
+        Self {
+            rom_hash,
+            seed,
+            clock_frequency,
+            ticks_per_timer: TICKS_PER_TIMER,
+            timebase: Timebase::Cycles,
+            fault_policy: FaultPolicy::default(),
+            rng_position: 0,
+            platform: PLATFORM.to_string(),
+            emulator: EMULATOR.to_string(),
+            author: None,
+            comment: None,
+        }
+    }
+}
+
+/// A recording: the header, the state it starts from (power-on if none), the
+/// input events and resets keyed by the header's [`Timebase`] and
+/// [`SaveState::checksum`]s taken along the way.
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub start: Option<SaveState>,
+    pub events: Vec<(u64, InputEvent)>,
+    /// Applied before the events at the same time
+    pub resets: Vec<u64>,
+    pub checkpoints: Vec<(u64, u64)>,
+}
+
+impl Movie {
+    pub fn new(header: MovieHeader) -> Self {
+        Self {
+            header,
+            start: None,
+            events: vec![],
+            resets: vec![],
+            checkpoints: vec![],
+        }
+    }
+
+    pub fn encode(&self, encoding: MovieEncoding) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut writer = MovieWriter::new(&mut bytes, encoding, &self.header, self.start.as_ref())
+            .expect("writing to a Vec can't fail");
+        // Already in the movie's timebase
+        for &(time, event) in &self.events {
+            writer
+                .write_record(time, event)
+                .expect("writing to a Vec can't fail");
+        }
+        for &time in &self.resets {
+            writer
+                .write_reset_record(time)
+                .expect("writing to a Vec can't fail");
+        }
+        for &(clk, checksum) in &self.checkpoints {
+            writer
+                .write_checkpoint(clk, checksum)
+                .expect("writing to a Vec can't fail");
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        match MovieEncoding::detect(bytes) {
+            Some(MovieEncoding::Text) => decode_text(bytes),
+            Some(MovieEncoding::Binary) => decode_binary(bytes),
+            None => Err(movie_error("Not a movie file")),
+        }
+    }
+
+    fn start_clk(&self) -> u64 {
+        self.start.as_ref().map_or(0, |state| state.clk)
+    }
+
+    /// The events keyed by clk for a machine running at `ticks_per_timer`,
+    /// ready for the input queue.
+    pub fn cycle_events(&self, ticks_per_timer: u64) -> Vec<(u64, InputEvent)> {
+        let start_clk = self.start_clk();
+        match self.header.timebase {
+            Timebase::Cycles if self.header.ticks_per_timer == ticks_per_timer => {
+                self.events.clone()
+            }
+            Timebase::Cycles => {
+                let events = self
+                    .events
+                    .iter()
+                    .map(|&(clk, event)| (clk.saturating_sub(start_clk), event));
+                retime(events, self.header.ticks_per_timer, ticks_per_timer)
+                    .into_iter()
+                    .map(|(clk, event)| (clk.saturating_add(start_clk), event))
+                    .collect()
+            }
+            Timebase::Frames => from_frames(self.events.iter().copied(), ticks_per_timer)
+                .into_iter()
+                .map(|(clk, event)| (clk.saturating_add(start_clk), event))
+                .collect(),
+        }
+    }
+
+    /// The resets keyed by clk, like [`Self::cycle_events`].
+    pub fn cycle_resets(&self, ticks_per_timer: u64) -> Vec<u64> {
+        let start_clk = self.start_clk();
+        let (from, to) = (self.header.ticks_per_timer.max(1), ticks_per_timer.max(1));
+        self.resets
+            .iter()
+            .map(|&time| match self.header.timebase {
+                Timebase::Cycles => {
+                    start_clk.saturating_add(retime_clk(time.saturating_sub(start_clk), from, to))
+                }
+                Timebase::Frames => start_clk.saturating_add(time.saturating_mul(to)),
+            })
+            .collect()
+    }
+
+    /// Converts the movie to `timebase` at `ticks_per_timer`. Checkpoints only
+    /// survive if the cycles they were taken at are unchanged.
+    pub fn retimed(&self, timebase: Timebase, ticks_per_timer: u64) -> Self {
+        let start_clk = self.start_clk();
+        let cycles = self.cycle_events(ticks_per_timer);
+        let events = match timebase {
+            Timebase::Cycles => cycles,
+            Timebase::Frames => to_frames(
+                cycles
+                    .into_iter()
+                    .map(|(clk, event)| (clk.saturating_sub(start_clk), event)),
+                ticks_per_timer,
+            ),
+        };
+        let resets = self
+            .cycle_resets(ticks_per_timer)
+            .into_iter()
+            .map(|clk| match timebase {
+                Timebase::Cycles => clk,
+                Timebase::Frames => clk.saturating_sub(start_clk) / ticks_per_timer.max(1),
+            })
+            .collect();
+        let unchanged = self.header.timebase == Timebase::Cycles
+            && timebase == Timebase::Cycles
+            && self.header.ticks_per_timer == ticks_per_timer;
+        Self {
+            header: MovieHeader {
+                ticks_per_timer,
+                timebase,
+                ..self.header.clone()
+            },
+            start: self.start.clone(),
+            events,
+            resets,
+            checkpoints: if unchanged {
+                self.checkpoints.clone()
+            } else {
+                vec![]
+            },
+        }
+    }
+}
+
+/// Maps events keyed by clk to the frame (timer tick) they occurred in.
+/// Several events may share a frame, in their original order.
+pub fn to_frames(
+    events: impl IntoIterator<Item = (u64, InputEvent)>,
+    ticks_per_timer: u64,
+) -> Vec<(u64, InputEvent)> {
+    let ticks_per_timer = ticks_per_timer.max(1);
+    events
+        .into_iter()
+        .map(|(clk, event)| (clk / ticks_per_timer, event))
+        .collect()
+}
+
+/// Anchors each event on the first cycle of its frame. Events sharing a frame
+/// are spread one cycle apart so that the game sees them in order.
+pub fn from_frames(
+    events: impl IntoIterator<Item = (u64, InputEvent)>,
+    ticks_per_timer: u64,
+) -> Vec<(u64, InputEvent)> {
+    let ticks_per_timer = ticks_per_timer.max(1);
+    spread(
+        events
+            .into_iter()
+            .map(|(frame, event)| (frame.saturating_mul(ticks_per_timer), event)),
+    )
+}
+
+/// Moves events recorded at `from` ticks per timer to the same frame at `to`,
+/// keeping their offset within the frame in proportion.
+pub fn retime(
+    events: impl IntoIterator<Item = (u64, InputEvent)>,
+    from: u64,
+    to: u64,
+) -> Vec<(u64, InputEvent)> {
+    let (from, to) = (from.max(1), to.max(1));
+    spread(
+        events
+            .into_iter()
+            .map(|(clk, event)| (retime_clk(clk, from, to), event)),
+    )
+}
+
+// The cycle at the same point of the same frame, ending up at the last cycle
+// rather than overflowing
+fn retime_clk(clk: u64, from: u64, to: u64) -> u64 {
+    let (frame, offset) = (clk / from, clk % from);
+    // Less than `to`, as the offset is less than `from`
+    let offset = (offset as u128 * to as u128 / from as u128) as u64;
+    frame.saturating_mul(to).saturating_add(offset)
+}
+
+// Keeps clks strictly increasing. The CPU applies every event due by a cycle at
+// once, so a press and release sharing one would never be seen as held.
+pub(crate) fn spread(events: impl Iterator<Item = (u64, InputEvent)>) -> Vec<(u64, InputEvent)> {
+    let mut next = 0;
+    events
+        .map(|(clk, event)| {
+            let clk = clk.max(next);
+            next = clk.saturating_add(1);
+            (clk, event)
+        })
+        .collect()
+}
+
+/// Checkpoints taken by the CPU every `interval` cycles, waiting to be written
+/// by the input driver. An interval of 0 disables them.
+#[derive(Debug, Default)]
+pub struct Checkpoints {
+    pub interval: u64,
+    pub pending: VecDeque<(u64, u64)>,
+}
+
+impl Checkpoints {
+    pub fn is_due(&self, clk: u64) -> bool {
+        self.interval > 0 && clk.is_multiple_of(self.interval)
+    }
+}
+
+/// Streams a movie as it is recorded, so that nothing is lost if the emulator
+/// stops abruptly.
+pub struct MovieWriter<W: Write> {
+    writer: W,
+    encoding: MovieEncoding,
+    // Frame-timed movies are written relative to the start at this rate
+    frames: Option<(u64, u64)>,
+    last_event: u64,
+    last_reset: u64,
+    last_checkpoint: u64,
+}
+
+impl<W: Write> MovieWriter<W> {
+    /// Writes the header of a new movie.
+    pub fn new(
+        mut writer: W,
+        encoding: MovieEncoding,
+        header: &MovieHeader,
+        start: Option<&SaveState>,
+    ) -> io::Result<Self> {
+        let bytes = match encoding {
+            MovieEncoding::Text => encode_text_header(header, start).into_bytes(),
+            MovieEncoding::Binary => encode_binary_header(header, start),
+        };
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        let start_clk = start.map_or(0, |state| state.clk);
+        let frames = (header.timebase == Timebase::Frames)
+            .then_some((start_clk, header.ticks_per_timer.max(1)));
+        Ok(Self {
+            writer,
+            encoding,
+            frames,
+            last_event: if frames.is_some() { 0 } else { start_clk },
+            last_reset: if frames.is_some() { 0 } else { start_clk },
+            last_checkpoint: start_clk,
+        })
+    }
+
+    /// Continues `movie`, which is already in `writer`. New events are timed
+    /// like the movie's, at its header's ticks per timer.
+    pub fn append(writer: W, encoding: MovieEncoding, movie: &Movie) -> Self {
+        let start_clk = movie.start_clk();
+        let frames = (movie.header.timebase == Timebase::Frames)
+            .then_some((start_clk, movie.header.ticks_per_timer.max(1)));
+        let first_event = if frames.is_some() { 0 } else { start_clk };
+        Self {
+            writer,
+            encoding,
+            frames,
+            last_event: movie.events.last().map_or(first_event, |(clk, _)| *clk),
+            last_reset: movie.resets.last().copied().unwrap_or(first_event),
+            last_checkpoint: movie.checkpoints.last().map_or(start_clk, |(clk, _)| *clk),
+        }
+    }
+
+    // The movie's time for `clk`
+    fn time(&self, clk: u64) -> u64 {
+        match self.frames {
+            Some((start_clk, ticks_per_timer)) => clk.saturating_sub(start_clk) / ticks_per_timer,
+            None => clk,
+        }
+    }
+
+    /// Takes the clk the event was applied at, whatever the movie's timebase.
+    pub fn write_event(&mut self, clk: u64, event: InputEvent) -> io::Result<()> {
+        self.write_record(self.time(clk), event)
+    }
+
+    /// Takes the clk of the reset, like [`Self::write_event`].
+    pub fn write_reset(&mut self, clk: u64) -> io::Result<()> {
+        self.write_reset_record(self.time(clk))
+    }
+
+    fn write_reset_record(&mut self, time: u64) -> io::Result<()> {
+        match self.encoding {
+            MovieEncoding::Text => writeln!(self.writer, "{RESET} {time}")?,
+            MovieEncoding::Binary => {
+                let mut bytes = vec![];
+                write_varint(&mut bytes, time.saturating_sub(self.last_reset));
+                bytes.push(RESET_TAG);
+                self.writer.write_all(&bytes)?;
+            }
+        }
+        self.last_reset = time;
+        self.writer.flush()
+    }
+
+    fn write_record(&mut self, time: u64, event: InputEvent) -> io::Result<()> {
+        match self.encoding {
+            MovieEncoding::Text => {
+                writeln!(
+                    self.writer,
+                    "{time} {} {}",
+                    event.key,
+                    kind_name(event.kind)
+                )?;
+            }
+            MovieEncoding::Binary => {
+                let mut bytes = vec![];
+                write_varint(&mut bytes, time.saturating_sub(self.last_event));
+                bytes.push((event.key as u8) << 1 | event.kind as u8);
+                self.writer.write_all(&bytes)?;
+            }
+        }
+        self.last_event = time;
+        self.writer.flush()
+    }
+
+    /// Checkpoints come from the CPU thread, so they can be written slightly
+    /// out of order with the events.
+    pub fn write_checkpoint(&mut self, clk: u64, checksum: u64) -> io::Result<()> {
+        // Taken again while replaying the part of the movie that has them
+        if clk <= self.last_checkpoint || self.frames.is_some() {
+            return Ok(());
+        }
+
+        match self.encoding {
+            MovieEncoding::Text => {
+                writeln!(self.writer, "{CHECKPOINT} {clk} {checksum:016x}")?;
+            }
+            MovieEncoding::Binary => {
+                let mut bytes = vec![];
+                write_varint(&mut bytes, clk.saturating_sub(self.last_checkpoint));
+                bytes.push(CHECKPOINT_TAG);
+                bytes.extend(checksum.to_le_bytes());
+                self.writer.write_all(&bytes)?;
+            }
+        }
+        self.last_checkpoint = clk;
+        self.writer.flush()
+    }
+}
+
+fn movie_error(message: impl Into<String>) -> Chip8Error {
+    Chip8Error::MovieError(message.into())
+}
+
+fn kind_name(kind: InputKind) -> &'static str {
+    match kind {
+        InputKind::Press => "press",
+        InputKind::Release => "release",
+    }
+}
+
+fn key_from_index(index: u8) -> Result<Key, Chip8Error> {
+    let c = char::from_digit(index as u32, 16).map(|c| c.to_ascii_uppercase());
+    c.and_then(|c| Key::try_from(c).ok())
+        .ok_or_else(|| movie_error(format!("Invalid key index {index}")))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, byte| {
+        let _ = write!(s, "{byte:02x}");
+        s
+    })
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, Chip8Error> {
+    if !s.len().is_multiple_of(2) {
+        return Err(movie_error("Odd number of hex digits"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| movie_error("Invalid hex digits"))
+        })
+        .collect()
+}
+
+fn encode_text_header(header: &MovieHeader, start: Option<&SaveState>) -> String {
+    let mut s = format!("{TEXT_MAGIC} {MOVIE_VERSION}\n");
+    let _ = writeln!(s, "rom {}", header.rom_hash);
+    let _ = writeln!(s, "seed {}", header.seed);
+    let _ = writeln!(s, "clock-frequency {}", header.clock_frequency);
+    let _ = writeln!(s, "ticks-per-timer {}", header.ticks_per_timer);
+    let _ = writeln!(s, "timebase {}", header.timebase.name());
+    if header.fault_policy != FaultPolicy::default() {
+        let _ = writeln!(s, "on-fault {}", header.fault_policy);
+    }
+    if header.rng_position > 0 {
+        let _ = writeln!(s, "rng-position {}", header.rng_position);
+    }
+    let _ = writeln!(s, "platform {}", header.platform);
+    let _ = writeln!(s, "emulator {}", header.emulator);
+    if let Some(author) = &header.author {
+        let _ = writeln!(s, "author {}", author.replace('\n', " "));
+    }
+    if let Some(comment) = &header.comment {
+        let _ = writeln!(s, "comment {}", comment.replace('\n', " "));
+    }
+    if let Some(state) = start {
+        let _ = writeln!(s, "state {}", hex(&state.to_bytes()));
+    }
+    let _ = writeln!(s, "{EVENTS_MARKER}");
+    s
+}
+
+fn decode_text(bytes: &[u8]) -> Result<Movie, Chip8Error> {
+    let text = std::str::from_utf8(bytes).map_err(|e| movie_error(e.to_string()))?;
+    let mut lines = text.lines().enumerate();
+
+    let version = lines
+        .next()
+        .and_then(|(_, line)| line.strip_prefix(TEXT_MAGIC))
+        .and_then(|version| version.trim().parse::<u32>().ok());
+    if version != Some(MOVIE_VERSION) {
+        return Err(movie_error(format!(
+            "Unsupported movie version, expected {MOVIE_VERSION}"
+        )));
+    }
+
+    let mut fields = Vec::new();
+    for (_, line) in lines.by_ref() {
+        if line.trim() == EVENTS_MARKER {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        fields.push((key.to_string(), value.trim().to_string()));
+    }
+
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+    let required = |name: &str| field(name).ok_or_else(|| movie_error(format!("Missing {name}")));
+    let number = |name: &str| {
+        required(name)?
+            .parse::<u64>()
+            .map_err(|e| movie_error(format!("Invalid {name}: {e}")))
+    };
+
+    let timebase = match required("timebase")?.as_str() {
+        "cycles" => Timebase::Cycles,
+        "frames" => Timebase::Frames,
+        other => return Err(movie_error(format!("Invalid timebase: {other}"))),
+    };
+    let header = MovieHeader {
+        rom_hash: required("rom")?,
+        seed: number("seed")?,
+        clock_frequency: number("clock-frequency")?,
+        ticks_per_timer: number("ticks-per-timer")?,
+        timebase,
+        fault_policy: field("on-fault")
+            .map(|policy| policy.parse().map_err(movie_error))
+            .transpose()?
+            .unwrap_or_default(),
+        rng_position: match field("rng-position") {
+            Some(_) => number("rng-position")?,
+            None => 0,
+        },
+        platform: required("platform")?,
+        emulator: required("emulator")?,
+        author: field("author"),
+        comment: field("comment"),
+    };
+    let start = field("state")
+        .map(|state| SaveState::from_bytes(&unhex(&state)?))
+        .transpose()?;
+
+    let mut events = vec![];
+    let mut resets = vec![];
+    let mut checkpoints = vec![];
+    for (i, line) in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || movie_error(format!("Invalid event on line {}: {line}", i + 1));
+        let out_of_order = || movie_error(format!("Out of order event on line {}: {line}", i + 1));
+
+        if let Some(checkpoint) = line.strip_prefix(CHECKPOINT) {
+            let mut parts = checkpoint.split_whitespace();
+            let (Some(clk), Some(checksum), None) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid());
+            };
+            let clk = clk.parse().map_err(|_| invalid())?;
+            let checksum = u64::from_str_radix(checksum, 16).map_err(|_| invalid())?;
+            if checkpoints.last().is_some_and(|&(last, _)| clk <= last) {
+                return Err(out_of_order());
+            }
+            checkpoints.push((clk, checksum));
+            continue;
+        }
+        if let Some(reset) = line.strip_prefix(RESET) {
+            let time = reset.trim().parse().map_err(|_| invalid())?;
+            if resets.last().is_some_and(|&last| time < last) {
+                return Err(out_of_order());
+            }
+            resets.push(time);
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let (Some(clk), Some(key), Some(kind), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let clk = clk.parse().map_err(|_| invalid())?;
+        let key = match key.chars().collect::<Vec<_>>()[..] {
+            [c] => Key::try_from(c.to_ascii_uppercase()).map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        let kind = match kind {
+            "press" => InputKind::Press,
+            "release" => InputKind::Release,
+            _ => return Err(invalid()),
+        };
+        if events.last().is_some_and(|&(last, _)| clk < last) {
+            return Err(out_of_order());
+        }
+        events.push((clk, InputEvent { key, kind }));
+    }
+
+    Ok(Movie {
+        header,
+        start,
+        events,
+        resets,
+        checkpoints,
+    })
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    write_varint(bytes, s.len() as u64);
+    bytes.extend(s.as_bytes());
+}
+
+fn encode_binary_header(header: &MovieHeader, start: Option<&SaveState>) -> Vec<u8> {
+    let mut bytes = BINARY_MAGIC.to_vec();
+    write_varint(&mut bytes, MOVIE_VERSION as u64);
+    write_string(&mut bytes, &header.rom_hash);
+    write_varint(&mut bytes, header.seed);
+    write_varint(&mut bytes, header.clock_frequency);
+    write_varint(&mut bytes, header.ticks_per_timer);
+    bytes.push(header.timebase as u8);
+    for (_, action) in header.fault_policy.rules() {
+        bytes.push(action as u8);
+    }
+    write_string(&mut bytes, &header.platform);
+    write_string(&mut bytes, &header.emulator);
+    // Optional strings are written empty when missing
+    write_string(&mut bytes, header.author.as_deref().unwrap_or_default());
+    write_string(&mut bytes, header.comment.as_deref().unwrap_or_default());
+    match start {
+        Some(state) => {
+            let state = state.to_bytes();
+            write_varint(&mut bytes, state.len() as u64);
+            bytes.extend(state);
+        }
+        None => write_varint(&mut bytes, 0),
+    }
+    write_varint(&mut bytes, header.rng_position);
+    bytes
+}
+
+// Reads from the binary encoding, failing on truncated input
+struct BinaryReader<'a>(&'a [u8]);
+
+impl<'a> BinaryReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Chip8Error> {
+        if n > self.0.len() {
+            return Err(movie_error("Truncated movie"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Chip8Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn varint(&mut self) -> Result<u64, Chip8Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            // Only one bit of the last byte fits
+            if shift == 63 && byte > 1 {
+                break;
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(movie_error("Invalid varint"))
+    }
+
+    fn string(&mut self) -> Result<String, Chip8Error> {
+        let len = self.varint()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| movie_error(e.to_string()))
+    }
+}
+
+fn decode_binary(bytes: &[u8]) -> Result<Movie, Chip8Error> {
+    let mut reader = BinaryReader(&bytes[BINARY_MAGIC.len()..]);
+    let version = reader.varint()?;
+    if version != MOVIE_VERSION as u64 {
+        return Err(movie_error(format!(
+            "Unsupported movie version {version}, expected {MOVIE_VERSION}"
+        )));
+    }
+
+    let rom_hash = reader.string()?;
+    let seed = reader.varint()?;
+    let clock_frequency = reader.varint()?;
+    let ticks_per_timer = reader.varint()?;
+    let timebase = match reader.byte()? {
+        0 => Timebase::Cycles,
+        1 => Timebase::Frames,
+        other => return Err(movie_error(format!("Invalid timebase {other}"))),
+    };
+    let mut fault_policy = FaultPolicy::default();
+    for (kind, _) in FaultPolicy::default().rules() {
+        fault_policy.set(kind, reader.byte()?.try_into()?);
+    }
+    let platform = reader.string()?;
+    let emulator = reader.string()?;
+    let author = Some(reader.string()?).filter(|s| !s.is_empty());
+    let comment = Some(reader.string()?).filter(|s| !s.is_empty());
+    let state_len = reader.varint()? as usize;
+    let start = match state_len {
+        0 => None,
+        len => Some(SaveState::from_bytes(reader.take(len)?)?),
+    };
+    let rng_position = reader.varint()?;
+
+    let mut events = vec![];
+    let mut resets = vec![];
+    let mut checkpoints = vec![];
+    let start_clk = start.as_ref().map_or(0, |state| state.clk);
+    let first_event = match timebase {
+        Timebase::Cycles => start_clk,
+        Timebase::Frames => 0,
+    };
+    let (mut event_clk, mut reset_clk, mut checkpoint_clk) = (first_event, first_event, start_clk);
+    while !reader.is_empty() {
+        let delta = reader.varint()?;
+        let byte = reader.byte()?;
+        let advance = |clk: u64| {
+            clk.checked_add(delta)
+                .ok_or_else(|| movie_error("Clk out of range"))
+        };
+        if byte == CHECKPOINT_TAG {
+            checkpoint_clk = advance(checkpoint_clk)?;
+            let checksum = u64::from_le_bytes(reader.array()?);
+            checkpoints.push((checkpoint_clk, checksum));
+        } else if byte == RESET_TAG {
+            reset_clk = advance(reset_clk)?;
+            resets.push(reset_clk);
+        } else {
+            event_clk = advance(event_clk)?;
+            let key = key_from_index(byte >> 1)?;
+            let kind = InputKind::try_from(byte & 1)?;
+            events.push((event_clk, InputEvent { key, kind }));
+        }
+    }
+
+    Ok(Movie {
+        header: MovieHeader {
+            rom_hash,
+            seed,
+            clock_frequency,
+            ticks_per_timer,
+            timebase,
+            fault_policy,
+            rng_position,
+            platform,
+            emulator,
+            author,
+            comment,
+        },
+        start,
+        events,
+        resets,
+        checkpoints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fault::{FaultAction, FaultKind},
+        state::SAVE_STATE_SIZE,
+    };
+
+    fn event(key: char, kind: InputKind) -> InputEvent {
+        InputEvent {
+            key: Key::try_from(key).unwrap(),
+            kind,
+        }
+    }
+
+    // InputEvent can't be compared as is
+    fn records(events: &[(u64, InputEvent)]) -> Vec<(u64, u8, InputKind)> {
+        events
+            .iter()
+            .map(|&(clk, event)| (clk, event.key as u8, event.kind))
+            .collect()
+    }
+
+    fn sample() -> Movie {
+        let mut header = MovieHeader::new("ab".repeat(20), 42, 700);
+        header
+            .fault_policy
+            .set(FaultKind::MemoryAccessOutOfBounds, FaultAction::Skip);
+        header.rng_position = 7;
+        header.author = Some("someone".to_string());
+        header.comment = Some("two\nlines".to_string());
+        let mut start = SaveState::from_bytes(&[0; SAVE_STATE_SIZE]).unwrap();
+        start.clk = 1000;
+
+        let mut movie = Movie::new(header);
+        movie.start = Some(start);
+        movie.events = vec![
+            (1000, event('5', InputKind::Press)),
+            (1003, event('5', InputKind::Release)),
+            (1003, event('F', InputKind::Press)),
+            (200_000, event('F', InputKind::Release)),
+        ];
+        movie.resets = vec![1002, 150_000];
+        movie.checkpoints = vec![(1100, 0xDEAD_BEEF), (1200, u64::MAX)];
+        movie
+    }
+
+    #[test]
+    fn round_trips_both_encodings() {
+        let movie = sample();
+        for encoding in [MovieEncoding::Text, MovieEncoding::Binary] {
+            let bytes = movie.encode(encoding);
+            assert_eq!(MovieEncoding::detect(&bytes), Some(encoding));
+            let decoded = Movie::decode(&bytes).unwrap();
+
+            let mut header = movie.header.clone();
+            if encoding == MovieEncoding::Text {
+                header.comment = Some("two lines".to_string());
+            }
+            assert_eq!(decoded.header, header);
+            assert_eq!(decoded.start, movie.start);
+            assert_eq!(records(&decoded.events), records(&movie.events));
+            assert_eq!(decoded.resets, movie.resets);
+            assert_eq!(decoded.checkpoints, movie.checkpoints);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let movie = sample();
+        let header_len = Movie {
+            events: vec![],
+            resets: vec![],
+            checkpoints: vec![],
+            ..movie.clone()
+        }
+        .encode(MovieEncoding::Binary)
+        .len();
+        let bytes = movie.encode(MovieEncoding::Binary);
+        for len in BINARY_MAGIC.len()..header_len {
+            assert!(Movie::decode(&bytes[..len]).is_err(), "cut at {len}");
+        }
+        // In the middle of the last checksum
+        assert!(Movie::decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_binary() {
+        let header = MovieHeader::new("ab".repeat(20), 0, 700);
+        let bytes = Movie::new(header).encode(MovieEncoding::Binary);
+
+        let mut overflow = bytes.clone();
+        write_varint(&mut overflow, u64::MAX);
+        overflow.push(0x01);
+        write_varint(&mut overflow, 1);
+        overflow.push(0x00);
+        assert!(Movie::decode(&overflow).is_err());
+
+        let mut bad_key = bytes.clone();
+        write_varint(&mut bad_key, 0);
+        bad_key.push(0x40);
+        assert!(Movie::decode(&bad_key).is_err());
+
+        let mut bad_version = BINARY_MAGIC.to_vec();
+        write_varint(&mut bad_version, MOVIE_VERSION as u64 + 1);
+        assert!(Movie::decode(&bad_version).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_text() {
+        let text = String::from_utf8(sample().encode(MovieEncoding::Text)).unwrap();
+        let decode = |text: String| Movie::decode(text.as_bytes());
+        assert!(decode(text.clone()).is_ok());
+
+        assert!(decode(text.replacen("chip8-movie 1", "chip8-movie 2", 1)).is_err());
+        assert!(decode(text.replacen("timebase cycles\n", "", 1)).is_err());
+        assert!(decode(text.replacen("1000 5 press", "1000 G press", 1)).is_err());
+        assert!(decode(text.replacen("1000 5 press", "1000 5 hold", 1)).is_err());
+        assert!(decode(format!("{text}1003 1 press\n")).is_err());
+        assert!(decode(format!("{text}{RESET} 1\n")).is_err());
+        assert!(decode(format!("{text}{CHECKPOINT} 1200 0\n")).is_err());
+        assert!(decode(format!("{text}99999999999999999999 1 press\n")).is_err());
+    }
+
+    #[test]
+    fn anchors_frames_on_their_first_cycle() {
+        let frames = [
+            (0, event('1', InputKind::Press)),
+            (0, event('1', InputKind::Release)),
+            (2, event('2', InputKind::Press)),
+            (u64::MAX, event('2', InputKind::Release)),
+        ];
+        assert_eq!(
+            records(&from_frames(frames, 8))
+                .iter()
+                .map(|&(clk, ..)| clk)
+                .collect::<Vec<_>>(),
+            [0, 1, 16, u64::MAX]
+        );
+        // Saturated frames can't come back
+        assert_eq!(
+            records(&to_frames(from_frames(frames, 8), 8))[..3],
+            records(&frames)[..3]
+        );
+    }
+
+    #[test]
+    fn retimes_within_the_frame() {
+        let events = [
+            (20, event('1', InputKind::Press)),
+            (21, event('1', InputKind::Release)),
+            (23, event('2', InputKind::Press)),
+        ];
+        let clks = |events: Vec<(u64, InputEvent)>| {
+            events.into_iter().map(|(clk, _)| clk).collect::<Vec<_>>()
+        };
+        // Frame 2, at half, five eighths and seven eighths of it
+        assert_eq!(clks(retime(events, 8, 16)), [40, 42, 46]);
+        // The first two land on the same cycle and are spread apart
+        assert_eq!(clks(retime(events, 8, 4)), [10, 11, 12]);
+        assert_eq!(retime_clk(u64::MAX, 1, 2), u64::MAX);
+        assert_eq!(retime_clk(u64::MAX, 0x10, 0x10), u64::MAX);
+    }
+
+    #[test]
+    fn retimes_movies() {
+        let movie = sample();
+        let frames = movie.retimed(Timebase::Frames, 8);
+        assert_eq!(frames.header.timebase, Timebase::Frames);
+        assert_eq!(frames.events[0].0, 0);
+        assert_eq!(frames.events[3].0, (200_000 - 1000) / 8);
+        assert_eq!(frames.resets, [0, (150_000 - 1000) / 8]);
+        assert!(frames.checkpoints.is_empty());
+
+        // Back on the first cycle of each frame, after the start
+        let cycles = frames.retimed(Timebase::Cycles, 8);
+        assert_eq!(
+            cycles
+                .events
+                .iter()
+                .map(|(clk, _)| *clk)
+                .collect::<Vec<_>>(),
+            [1000, 1001, 1002, 1000 + (200_000 - 1000) / 8 * 8]
+        );
+        assert_eq!(cycles.cycle_resets(16), [1000, 1000 + 149_000 / 8 * 16]);
+
+        // Nothing changes, so the checkpoints still hold
+        let same = movie.retimed(Timebase::Cycles, movie.header.ticks_per_timer);
+        assert_eq!(same.checkpoints, movie.checkpoints);
+        assert!(movie.retimed(Timebase::Cycles, 16).checkpoints.is_empty());
+    }
+
+    #[test]
+    fn reads_varint_edge_cases() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, value);
+            let mut reader = BinaryReader(&bytes);
+            assert_eq!(reader.varint().unwrap(), value);
+            assert!(reader.is_empty());
+        }
+
+        let varint = |bytes: &[u8]| BinaryReader(bytes).varint();
+        let mut max = vec![0xFF; 9];
+        max.push(0x01);
+        assert_eq!(varint(&max).unwrap(), u64::MAX);
+        // Bits past the 64th
+        max[9] = 0x02;
+        assert!(varint(&max).is_err());
+        // Longer than any u64
+        assert!(varint(&[0x80; 10].iter().copied().chain([0]).collect::<Vec<_>>()).is_err());
+        assert!(varint(&[]).is_err());
+        assert!(varint(&[0x80]).is_err());
+    }
+}
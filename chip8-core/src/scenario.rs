@@ -0,0 +1,638 @@
+//! Scripted tests: an input script with expectations on the machine's state,
+//! checked at the point of the script where they appear.
+//!
+//! ```text
+//! seed 42
+//! wait 60 frames
+//! expect V3 == 7
+//! tap 5
+//! wait 10 frames
+//! expect mem[0x300..0x303] == 01 02 03
+//! glyph S F0 80 F0 10 F0
+//! expect screen contains text "SCORE 10"
+//! expect screen matches golden.txt
+//! expect sound active
+//! ```
+//!
+//! `seed`, `clock-frequency`, `ticks-per-timer` and `glyph` apply to the whole
+//! run wherever they are. Scenarios always run headless, as fast as possible.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    ops::Range,
+    path::Path,
+};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::{
+    constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH, FONTSET, FONT_SIZE, MEMORY_SIZE, TICKS_PER_TIMER},
+    cpu::SimpleCpu,
+    error::Chip8Error,
+    input::InputEvent,
+    script::{self, script_error, split_lines, Timing},
+    state::SaveState,
+    Chip8,
+};
+
+/// Frequency of scenarios that don't set one, for durations in seconds.
+pub const DEFAULT_CLOCK_FREQUENCY: u64 = 560;
+
+const EXPECT: &str = "expect";
+const SETTINGS: [&str; 3] = ["seed", "clock-frequency", "ticks-per-timer"];
+const GLYPH: &str = "glyph";
+
+// Widest gap between two letters of a word
+const MAX_LETTER_GAP: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Register(Register, Comparison, u64),
+    Memory(Range<usize>, Comparison, Vec<u8>),
+    /// Text drawn with the glyphs of its characters, on a single line
+    ScreenText(String, Vec<Glyph>),
+    /// Path of a file in the format of [`SaveState::screen_text`], where spaces
+    /// are also off
+    ScreenGolden(String),
+    Sound(bool),
+}
+
+/// A character as the ROM draws it: sprite rows, the leftmost pixel in the
+/// top bit. Hex digits default to the built-in font, as drawn with FX29.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glyph {
+    pub c: char,
+    pub rows: Vec<u8>,
+}
+
+impl Glyph {
+    fn font(c: char) -> Option<Self> {
+        let start = c.to_digit(16)? as usize * FONT_SIZE;
+        Some(Self {
+            c,
+            rows: FONTSET[start..start + FONT_SIZE].to_vec(),
+        })
+    }
+
+    // Up to the rightmost column with a pixel in it
+    fn width(&self) -> usize {
+        let columns = self.rows.iter().fold(0, |acc, row| acc | row);
+        8 - columns.trailing_zeros() as usize
+    }
+}
+
+/// An expectation and where it is checked.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub clk: u64,
+    pub line: usize,
+    pub expectation: Expectation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub clk: u64,
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub checks: usize,
+    pub failures: Vec<Failure>,
+    /// Cycles run
+    pub clk: u64,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub seed: u64,
+    pub timing: Timing,
+    pub events: Vec<(u64, InputEvent)>,
+    pub checks: Vec<Check>,
+}
+
+impl Scenario {
+    pub fn parse(source: &str) -> Result<Self, Chip8Error> {
+        // Settings come first as durations depend on them
+        let mut seed = 0;
+        let mut timing = Timing {
+            clk_freq: DEFAULT_CLOCK_FREQUENCY,
+            ticks_per_timer: TICKS_PER_TIMER,
+        };
+        let mut glyphs = vec![];
+        for (line, text) in split_lines(source) {
+            let (name, value) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+            if name == GLYPH {
+                let glyph = parse_glyph(value).map_err(|e| script_error(line, e))?;
+                glyphs.retain(|defined: &Glyph| defined.c != glyph.c);
+                glyphs.push(glyph);
+                continue;
+            }
+            if !SETTINGS.contains(&name) {
+                continue;
+            }
+            let value = parse_number(value.trim())
+                .ok_or_else(|| script_error(line, format!("Invalid {name}: {value}")))?;
+            match name {
+                "seed" => seed = value,
+                "clock-frequency" => timing.clk_freq = value,
+                _ => timing.ticks_per_timer = value.max(1),
+            }
+        }
+
+        let keywords = [EXPECT, GLYPH, SETTINGS[0], SETTINGS[1], SETTINGS[2]];
+        let compiled = script::compile(source, timing, &keywords)?;
+        let checks = compiled
+            .directives
+            .into_iter()
+            .filter_map(|directive| {
+                let condition = directive.text.strip_prefix(EXPECT)?;
+                Some(
+                    parse_expectation(condition.trim(), &glyphs)
+                        .map(|expectation| Check {
+                            clk: directive.clk,
+                            line: directive.line,
+                            expectation,
+                        })
+                        .map_err(|e| script_error(directive.line, e)),
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            seed,
+            timing,
+            events: compiled.events,
+            checks,
+        })
+    }
+
+    /// Runs `rom` until the last check, reading golden files relative to `dir`.
+    /// Failed expectations are reported, errors of the machine returned.
+    pub fn run(&self, rom: &[u8], dir: &Path) -> Result<Report, Chip8Error> {
+        let rng = ChaCha12Rng::seed_from_u64(self.seed);
+        let cpu = SimpleCpu::new(self.timing.clk_freq, rng)
+            .with_ticks_per_timer(self.timing.ticks_per_timer);
+        let mut chip8 = Chip8::new(cpu, self.events.clone());
+        chip8.load(rom)?;
+
+        let mut report = Report {
+            checks: self.checks.len(),
+            ..Report::default()
+        };
+        for check in &self.checks {
+            while chip8.clk()? < check.clk {
+                chip8.step()?;
+            }
+            let state = chip8.save_state()?;
+            if let Err(message) = check.expectation.check(&state, dir) {
+                report.failures.push(Failure {
+                    clk: check.clk,
+                    line: check.line,
+                    message,
+                });
+            }
+        }
+        report.clk = chip8.clk()?;
+        Ok(report)
+    }
+}
+
+impl Expectation {
+    /// Describes the mismatch if `state` doesn't meet the expectation.
+    pub fn check(&self, state: &SaveState, dir: &Path) -> Result<(), String> {
+        match self {
+            Self::Register(register, comparison, expected) => {
+                let actual = match *register {
+                    Register::V(x) => state.registers[x] as u64,
+                    Register::I => state.index_register as u64,
+                    Register::Pc => state.program_counter as u64,
+                    Register::Sp => state.stack_pointer as u64,
+                    Register::Dt => state.delay_timer as u64,
+                    Register::St => state.sound_timer as u64,
+                };
+                if comparison.holds(actual.cmp(expected)) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{register} is {actual:#X}, expected {comparison} {expected:#X}"
+                    ))
+                }
+            }
+            Self::Memory(range, comparison, expected) => {
+                let actual = &state.memory[range.clone()];
+                if comparison.holds(actual.cmp(expected.as_slice())) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "mem[{:#05X}..{:#05X}] is {}, expected {comparison} {}",
+                        range.start,
+                        range.end,
+                        hex_bytes(actual),
+                        hex_bytes(expected)
+                    ))
+                }
+            }
+            Self::ScreenText(text, glyphs) => {
+                if screen_contains(&state.frame_buffer, text, glyphs) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "screen doesn't contain \"{text}\":\n{}",
+                        state.screen_text()
+                    ))
+                }
+            }
+            Self::ScreenGolden(path) => {
+                let actual = state.screen_text();
+                let golden = fs::read_to_string(dir.join(path))
+                    .map_err(|e| format!("can't read {path}: {e}, screen is:\n{actual}"))?;
+                if parse_golden(&golden) == state.frame_buffer {
+                    Ok(())
+                } else {
+                    Err(format!("screen doesn't match {path}, it is:\n{actual}"))
+                }
+            }
+            Self::Sound(active) => match (state.sound_timer > 0, active) {
+                (true, true) | (false, false) => Ok(()),
+                (true, false) => Err("sound is active".to_string()),
+                (false, true) => Err("sound is inactive".to_string()),
+            },
+        }
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V(x) => write!(f, "V{x:X}"),
+            Self::I => write!(f, "I"),
+            Self::Pc => write!(f, "PC"),
+            Self::Sp => write!(f, "SP"),
+            Self::Dt => write!(f, "DT"),
+            Self::St => write!(f, "ST"),
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        };
+        f.write_str(symbol)
+    }
+}
+
+impl Comparison {
+    fn holds(self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            Self::Eq => ordering.is_eq(),
+            Self::Ne => ordering.is_ne(),
+            Self::Lt => ordering.is_lt(),
+            Self::Le => ordering.is_le(),
+            Self::Gt => ordering.is_gt(),
+            Self::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_comparison(s: &str) -> Result<Comparison, String> {
+    match s {
+        "==" => Ok(Comparison::Eq),
+        "!=" => Ok(Comparison::Ne),
+        "<" => Ok(Comparison::Lt),
+        "<=" => Ok(Comparison::Le),
+        ">" => Ok(Comparison::Gt),
+        ">=" => Ok(Comparison::Ge),
+        _ => Err(format!("Invalid comparison: {s}")),
+    }
+}
+
+// `S F0 80 F0 10 F0`, the character quoted if it would start a comment
+fn parse_glyph(definition: &str) -> Result<Glyph, String> {
+    let mut words = definition.split_whitespace();
+    let unquoted = |c: &str| {
+        let c = c
+            .strip_prefix('"')
+            .and_then(|c| c.strip_suffix('"'))
+            .unwrap_or(c);
+        c.chars().collect::<Vec<_>>()
+    };
+    let c = match words.next().map(unquoted) {
+        Some(chars) if chars.len() == 1 => chars[0],
+        _ => return Err("Expected a single character".to_string()),
+    };
+    let rows = words
+        .map(|row| {
+            let row = row.trim_start_matches("0x");
+            u8::from_str_radix(row, 16).map_err(|_| format!("Invalid sprite row: {row}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if !(1..=15).contains(&rows.len()) {
+        return Err(format!("Glyph '{c}' needs 1 to 15 rows, like a sprite"));
+    }
+    if rows.iter().all(|row| *row == 0) {
+        return Err(format!("Glyph '{c}' is blank"));
+    }
+    Ok(Glyph { c, rows })
+}
+
+fn parse_expectation(condition: &str, glyphs: &[Glyph]) -> Result<Expectation, String> {
+    let words = condition.split_whitespace().collect::<Vec<_>>();
+    match words[..] {
+        ["sound", "active"] => Ok(Expectation::Sound(true)),
+        ["sound", "inactive"] => Ok(Expectation::Sound(false)),
+        ["screen", "contains", "text", ..] => {
+            let text = condition
+                .split_once('"')
+                .and_then(|(_, rest)| rest.rsplit_once('"'))
+                .map(|(text, _)| text.to_string())
+                .filter(|text| !text.trim().is_empty())
+                .ok_or("Expected quoted text")?;
+            let mut used = vec![];
+            for c in text.chars().filter(|c| !c.is_whitespace()) {
+                if used.iter().any(|glyph: &Glyph| glyph.c == c) {
+                    continue;
+                }
+                let glyph = glyphs
+                    .iter()
+                    .find(|glyph| glyph.c == c)
+                    .cloned()
+                    .or_else(|| Glyph::font(c))
+                    .ok_or_else(|| format!("No glyph for '{c}', define it with `glyph {c} ..`"))?;
+                used.push(glyph);
+            }
+            Ok(Expectation::ScreenText(text, used))
+        }
+        ["screen", "matches", path] => Ok(Expectation::ScreenGolden(path.to_string())),
+        [lhs, comparison, ref rhs @ ..] if lhs.starts_with("mem[") => {
+            let range = lhs
+                .strip_prefix("mem[")
+                .and_then(|lhs| lhs.strip_suffix(']'))
+                .and_then(parse_range)
+                .ok_or_else(|| format!("Invalid address range: {lhs}"))?;
+            let bytes = rhs
+                .iter()
+                .map(|byte| {
+                    let byte = byte.trim_start_matches("0x");
+                    u8::from_str_radix(byte, 16).map_err(|_| format!("Invalid byte: {byte}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if bytes.len() != range.len() {
+                return Err(format!(
+                    "{} bytes given for a range of {}",
+                    bytes.len(),
+                    range.len()
+                ));
+            }
+            Ok(Expectation::Memory(
+                range,
+                parse_comparison(comparison)?,
+                bytes,
+            ))
+        }
+        [lhs, comparison, rhs] => {
+            let register = match lhs.to_ascii_uppercase().as_str() {
+                "I" => Register::I,
+                "PC" => Register::Pc,
+                "SP" => Register::Sp,
+                "DT" => Register::Dt,
+                "ST" => Register::St,
+                v => v
+                    .strip_prefix('V')
+                    .filter(|x| x.len() == 1)
+                    .and_then(|x| usize::from_str_radix(x, 16).ok())
+                    .map(Register::V)
+                    .ok_or_else(|| format!("Unknown register: {lhs}"))?,
+            };
+            let value = parse_number(rhs).ok_or_else(|| format!("Invalid value: {rhs}"))?;
+            Ok(Expectation::Register(
+                register,
+                parse_comparison(comparison)?,
+                value,
+            ))
+        }
+        _ => Err(format!("Invalid expectation: {condition}")),
+    }
+}
+
+// `0x300`, `0x300..0x303` or `0x300..=0x302`
+fn parse_range(s: &str) -> Option<Range<usize>> {
+    let address = |s| usize::try_from(parse_number(s)?).ok();
+    let range = match s.split_once("..") {
+        None => {
+            let start = address(s)?;
+            start..start.checked_add(1)?
+        }
+        Some((start, end)) => {
+            let start = address(start)?;
+            match end.strip_prefix('=') {
+                Some(end) => start..address(end)?.checked_add(1)?,
+                None => start..address(end)?,
+            }
+        }
+    };
+    (range.start < range.end && range.end <= MEMORY_SIZE).then_some(range)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+type FrameBuffer = [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+
+fn parse_golden(golden: &str) -> FrameBuffer {
+    let mut frame_buffer = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+    for (row, line) in frame_buffer.iter_mut().zip(golden.lines()) {
+        for (pixel, c) in row.iter_mut().zip(line.chars()) {
+            *pixel = matches!(c, '#' | '█');
+        }
+    }
+    frame_buffer
+}
+
+fn glyph_at(frame_buffer: &FrameBuffer, glyph: &Glyph, x: usize, y: usize) -> bool {
+    let width = glyph.width();
+    if x + width > DISPLAY_WIDTH || y + glyph.rows.len() > DISPLAY_HEIGHT {
+        return false;
+    }
+    glyph.rows.iter().enumerate().all(|(dy, row)| {
+        (0..width).all(|dx| frame_buffer[y + dy][x + dx] == (row >> (7 - dx) & 1 == 1))
+    })
+}
+
+// Where `word` ends if it is drawn from (x, y), its letters up to
+// MAX_LETTER_GAP pixels apart
+fn word_at(frame_buffer: &FrameBuffer, word: &[&Glyph], x: usize, y: usize) -> Option<usize> {
+    let (first, rest) = word.split_first()?;
+    if !glyph_at(frame_buffer, first, x, y) {
+        return None;
+    }
+    rest.iter().try_fold(x + first.width(), |end, glyph| {
+        (end..=end + MAX_LETTER_GAP)
+            .find(|&x| glyph_at(frame_buffer, glyph, x, y))
+            .map(|x| x + glyph.width())
+    })
+}
+
+/// Whether `text` is drawn with `glyphs` on a single line. Words are looked
+/// for left to right.
+fn screen_contains(frame_buffer: &FrameBuffer, text: &str, glyphs: &[Glyph]) -> bool {
+    let words = text
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter_map(|c| glyphs.iter().find(|glyph| glyph.c == c))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    (0..DISPLAY_HEIGHT).any(|y| {
+        let mut from = 0;
+        words.iter().all(|word| {
+            match (from..DISPLAY_WIDTH).find_map(|x| word_at(frame_buffer, word, x, y)) {
+                Some(end) => {
+                    from = end;
+                    true
+                }
+                None => false,
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(frame_buffer: &mut FrameBuffer, glyph: &Glyph, x: usize, y: usize) {
+        for (dy, row) in glyph.rows.iter().enumerate() {
+            for dx in 0..8 {
+                frame_buffer[y + dy][x + dx] ^= row >> (7 - dx) & 1 == 1;
+            }
+        }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("0x300"), Some(0x300..0x301));
+        assert_eq!(parse_range("0x300..0x303"), Some(0x300..0x303));
+        assert_eq!(parse_range("0x300..=0x302"), Some(0x300..0x303));
+        assert_eq!(parse_range("0x303..0x300"), None);
+        assert_eq!(parse_range("0xFFF..=0x1000"), None);
+        assert_eq!(parse_range("0xFFFFFFFFFFFFFFFF"), None);
+        assert_eq!(parse_range("0..=0xFFFFFFFFFFFFFFFF"), None);
+    }
+
+    #[test]
+    fn parses_expectations() {
+        assert_eq!(
+            parse_expectation("v3 >= 0x10", &[]),
+            Ok(Expectation::Register(Register::V(3), Comparison::Ge, 16))
+        );
+        assert_eq!(
+            parse_expectation("mem[0x300..=0x301] != 01 0xFF", &[]),
+            Ok(Expectation::Memory(
+                0x300..0x302,
+                Comparison::Ne,
+                vec![1, 0xFF]
+            ))
+        );
+        assert_eq!(
+            parse_expectation("sound inactive", &[]),
+            Ok(Expectation::Sound(false))
+        );
+        assert!(parse_expectation("VG == 1", &[]).is_err());
+        assert!(parse_expectation("mem[0x300..0x302] == 01", &[]).is_err());
+        assert!(parse_expectation("screen contains text \"HI\"", &[]).is_err());
+    }
+
+    #[test]
+    fn keeps_comment_marks_in_quotes() {
+        let scenario = Scenario::parse(
+            "glyph \"#\" 50 F8 50 F8 50\n\
+             wait 2 frames // settle\n\
+             expect screen contains text \"#1\" # after two frames\n",
+        )
+        .unwrap();
+        assert_eq!(scenario.checks.len(), 1);
+        assert_eq!(scenario.checks[0].clk, 2 * TICKS_PER_TIMER);
+        let Expectation::ScreenText(text, glyphs) = &scenario.checks[0].expectation else {
+            panic!("not a screen text expectation");
+        };
+        assert_eq!(text, "#1");
+        assert_eq!(glyphs.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_glyphs() {
+        assert!(parse_glyph("AB F0").is_err());
+        assert!(parse_glyph("A").is_err());
+        assert!(parse_glyph("A 00 00").is_err());
+        assert!(parse_glyph("A G0").is_err());
+        assert_eq!(parse_glyph("\"#\" 50").map(|glyph| glyph.c), Ok('#'));
+    }
+
+    #[test]
+    fn finds_text_drawn_with_glyphs() {
+        let h = parse_glyph("H 90 90 F0 90 90").unwrap();
+        let i = parse_glyph("I E0 40 40 40 E0").unwrap();
+        let one = Glyph::font('1').unwrap();
+        let glyphs = [h.clone(), i.clone(), one.clone()];
+
+        let mut frame_buffer = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        draw(&mut frame_buffer, &h, 10, 4);
+        draw(&mut frame_buffer, &i, 15, 4);
+        draw(&mut frame_buffer, &one, 30, 4);
+
+        assert!(screen_contains(&frame_buffer, "HI", &glyphs));
+        assert!(screen_contains(&frame_buffer, "HI 1", &glyphs));
+        assert!(!screen_contains(&frame_buffer, "1 HI", &glyphs));
+        assert!(!screen_contains(&frame_buffer, "IH", &glyphs));
+    }
+}
@@ -0,0 +1,435 @@
+//! Input scripts, a hand-writable alternative to recorded movies:
+//!
+//! ```text
+//! # Start the game
+//! wait 120 frames
+//! tap 5
+//! repeat 3 {
+//!     press 4 for 10 frames
+//!     wait 500ms
+//! }
+//! hold 4,6 for 2s
+//! ```
+//!
+//! Statements run one after the other from clk 0: `wait`, `press ... for` and
+//! `hold` move time forward, `press` and `release` alone don't. Events that
+//! would share a cycle go one cycle apart, so that the program sees each of
+//! them. Durations are in `frames`, `cycles`, `s` or `ms`, and comments start
+//! with `#` or `//`.
+
+use std::fmt::Display;
+
+use crate::{
+    error::Chip8Error,
+    input::{InputEvent, InputKind},
+    keypad::Key,
+    movie::spread,
+};
+
+/// Frames a tapped key stays down, and then up before the next statement.
+pub const TAP_FRAMES: u64 = 2;
+/// Most statements a script may run once its repeats are expanded.
+pub const MAX_STATEMENTS: u64 = 1_000_000;
+
+/// Converts script time to cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub clk_freq: u64,
+    pub ticks_per_timer: u64,
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Wait(u64),
+    Press(Vec<Key>),
+    Release(Vec<Key>),
+    Hold(Vec<Key>, u64),
+    Tap(Vec<Key>),
+    Repeat(u64, Vec<Statement>),
+    Directive(usize, String),
+}
+
+/// A line left for the caller to interpret, with the clk the script reached
+/// when it was met.
+#[derive(Debug, Clone)]
+pub(crate) struct Directive {
+    pub clk: u64,
+    pub line: usize,
+    pub text: String,
+}
+
+pub(crate) struct Compiled {
+    pub events: Vec<(u64, InputEvent)>,
+    pub directives: Vec<Directive>,
+}
+
+/// Parses `source` into input events keyed by clk, ready for [`crate::Chip8::new`].
+pub fn parse(source: &str, timing: Timing) -> Result<Vec<(u64, InputEvent)>, Chip8Error> {
+    Ok(compile(source, timing, &[])?.events)
+}
+
+/// Like [`parse`], also accepting lines starting with one of `keywords`,
+/// which are returned as directives in the order they are reached.
+pub(crate) fn compile(
+    source: &str,
+    timing: Timing,
+    keywords: &[&str],
+) -> Result<Compiled, Chip8Error> {
+    let lines = split_lines(source);
+    let mut parser = Parser {
+        lines: &lines,
+        pos: 0,
+        timing,
+        keywords,
+    };
+    let statements = parser.block(false)?;
+    if expanded(&statements) > MAX_STATEMENTS {
+        return Err(Chip8Error::ScriptError(format!(
+            "Script runs more than {MAX_STATEMENTS} statements"
+        )));
+    }
+
+    let mut emitter = Emitter {
+        clk: 0,
+        timing,
+        events: vec![],
+        directives: vec![],
+    };
+    emitter.emit(&statements);
+    Ok(Compiled {
+        // A key released and pressed again in the same cycle would look held
+        events: spread(emitter.events.into_iter()),
+        directives: emitter.directives,
+    })
+}
+
+/// Whether `bytes` look like a script rather than another input format, i.e.
+/// text that doesn't start with a CSV header. Anything else is up to [`parse`]
+/// to reject.
+pub fn detect(bytes: &[u8]) -> bool {
+    let Ok(source) = std::str::from_utf8(bytes) else {
+        return false;
+    };
+    split_lines(source).first().is_some_and(|(_, line)| {
+        let word = line.split_whitespace().next().unwrap_or_default();
+        !word.contains(',')
+    })
+}
+
+// Statements run once repeats are expanded, counting a pass through an empty
+// block as one
+fn expanded(statements: &[Statement]) -> u64 {
+    statements
+        .iter()
+        .map(|statement| match statement {
+            Statement::Repeat(count, body) => count.saturating_mul(expanded(body).max(1)),
+            _ => 1,
+        })
+        .fold(0, u64::saturating_add)
+}
+
+pub(crate) fn script_error(line: usize, message: impl Display) -> Chip8Error {
+    Chip8Error::ScriptError(format!("line {line}: {message}"))
+}
+
+// Non-empty lines without comments, with their 1-based numbers. Braces get a
+// line of their own so that blocks can open on the line of their statement.
+// Quoted text is kept as is.
+pub(crate) fn split_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let mut split = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '#' if !quoted => break,
+                '/' if !quoted && chars.peek() == Some(&'/') => break,
+                '{' | '}' if !quoted => {
+                    split.extend(['\n', c, '\n']);
+                    continue;
+                }
+                _ => {}
+            }
+            split.push(c);
+        }
+        for part in split.lines() {
+            let part = part.trim();
+            if !part.is_empty() {
+                lines.push((i + 1, part.to_string()));
+            }
+        }
+    }
+    lines
+}
+
+struct Parser<'a> {
+    lines: &'a [(usize, String)],
+    pos: usize,
+    timing: Timing,
+    keywords: &'a [&'a str],
+}
+
+impl Parser<'_> {
+    // Statements up to the end of the script, or of the block if `nested`
+    fn block(&mut self, nested: bool) -> Result<Vec<Statement>, Chip8Error> {
+        let mut statements = vec![];
+        while let Some((number, line)) = self.lines.get(self.pos) {
+            self.pos += 1;
+            match line.as_str() {
+                "}" if nested => return Ok(statements),
+                "}" => return Err(script_error(*number, "Unmatched '}'")),
+                "{" => return Err(script_error(*number, "Block without repeat")),
+                _ => statements.push(self.statement(*number, line)?),
+            }
+        }
+        match (nested, self.lines.last()) {
+            (true, Some((number, _))) => Err(script_error(*number, "Missing '}'")),
+            _ => Ok(statements),
+        }
+    }
+
+    fn statement(&mut self, number: usize, line: &str) -> Result<Statement, Chip8Error> {
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let (keys, held_for) = match rest.split_once(" for ") {
+            Some((keys, duration)) => (keys, Some(duration)),
+            None => (rest, None),
+        };
+        let cycles = |duration: Option<&str>| {
+            let duration = duration.ok_or_else(|| script_error(number, "Missing duration"))?;
+            parse_duration(duration, self.timing).map_err(|e| script_error(number, e))
+        };
+
+        match word {
+            "wait" => Ok(Statement::Wait(cycles(Some(rest))?)),
+            "press" if held_for.is_some() => Ok(Statement::Hold(
+                parse_keys(keys, number)?,
+                cycles(held_for)?,
+            )),
+            "press" => Ok(Statement::Press(parse_keys(keys, number)?)),
+            "release" => Ok(Statement::Release(parse_keys(keys, number)?)),
+            "hold" => Ok(Statement::Hold(
+                parse_keys(keys, number)?,
+                cycles(held_for)?,
+            )),
+            "tap" => Ok(Statement::Tap(parse_keys(keys, number)?)),
+            "repeat" => {
+                let count: u64 = rest
+                    .parse()
+                    .map_err(|_| script_error(number, format!("Invalid repeat count: {rest}")))?;
+                match self.lines.get(self.pos) {
+                    Some((_, open)) if open == "{" => self.pos += 1,
+                    _ => return Err(script_error(number, "Expected '{' after repeat")),
+                }
+                let body = self.block(true)?;
+                if count.saturating_mul(expanded(&body).max(1)) > MAX_STATEMENTS {
+                    return Err(script_error(
+                        number,
+                        format!("Repeat runs more than {MAX_STATEMENTS} statements"),
+                    ));
+                }
+                Ok(Statement::Repeat(count, body))
+            }
+            _ if self.keywords.contains(&word) => {
+                Ok(Statement::Directive(number, line.to_string()))
+            }
+            _ => Err(script_error(
+                number,
+                format!("Unknown script keyword: {word}"),
+            )),
+        }
+    }
+}
+
+fn parse_keys(keys: &str, number: usize) -> Result<Vec<Key>, Chip8Error> {
+    keys.split(',')
+        .map(str::trim)
+        .map(|key| {
+            match key.chars().collect::<Vec<_>>()[..] {
+                [c] => Key::try_from(c.to_ascii_uppercase()).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| script_error(number, format!("Invalid key: {key}")))
+        })
+        .collect()
+}
+
+/// Parses `120 frames`, `30 cycles`, `2s` or `1.5 s`, `250ms` into cycles.
+pub fn parse_duration(duration: &str, timing: Timing) -> Result<u64, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid duration: {duration}"))?;
+
+    let per_second = match timing.clk_freq {
+        0 => Err("Durations in seconds need a clock frequency".to_string()),
+        clk_freq => Ok(clk_freq as f64),
+    };
+    let cycles = match unit.trim() {
+        "frame" | "frames" => value * timing.ticks_per_timer as f64,
+        "cycle" | "cycles" => value,
+        "s" | "sec" | "secs" | "second" | "seconds" => value * per_second?,
+        "ms" => value * per_second? / 1000.0,
+        "" => return Err(format!("Missing unit in duration: {duration}")),
+        unit => return Err(format!("Unknown unit: {unit}")),
+    };
+    Ok(cycles.round() as u64)
+}
+
+// Runs the statements from clk 0, collecting what they produce
+struct Emitter {
+    clk: u64,
+    timing: Timing,
+    events: Vec<(u64, InputEvent)>,
+    directives: Vec<Directive>,
+}
+
+impl Emitter {
+    fn push(&mut self, keys: &[Key], kind: InputKind) {
+        let clk = self.clk;
+        self.events
+            .extend(keys.iter().map(|&key| (clk, InputEvent { key, kind })));
+    }
+
+    fn emit(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match statement {
+                Statement::Wait(cycles) => self.clk = self.clk.saturating_add(*cycles),
+                Statement::Press(keys) => self.push(keys, InputKind::Press),
+                Statement::Release(keys) => self.push(keys, InputKind::Release),
+                Statement::Hold(keys, cycles) => {
+                    self.push(keys, InputKind::Press);
+                    self.clk = self.clk.saturating_add(*cycles);
+                    self.push(keys, InputKind::Release);
+                }
+                Statement::Tap(keys) => {
+                    let cycles = TAP_FRAMES.saturating_mul(self.timing.ticks_per_timer);
+                    self.push(keys, InputKind::Press);
+                    self.clk = self.clk.saturating_add(cycles);
+                    self.push(keys, InputKind::Release);
+                    self.clk = self.clk.saturating_add(cycles);
+                }
+                Statement::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.emit(body);
+                    }
+                }
+                Statement::Directive(line, text) => self.directives.push(Directive {
+                    clk: self.clk,
+                    line: *line,
+                    text: text.clone(),
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMING: Timing = Timing {
+        clk_freq: 600,
+        ticks_per_timer: 10,
+    };
+
+    fn events(source: &str) -> Vec<String> {
+        let events = parse(source, TIMING).unwrap();
+        events
+            .iter()
+            .map(|(clk, event)| format!("{clk} {} {:?}", event.key, event.kind))
+            .collect()
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("120 frames", TIMING), Ok(1200));
+        assert_eq!(parse_duration("1 frame", TIMING), Ok(10));
+        assert_eq!(parse_duration("30 cycles", TIMING), Ok(30));
+        assert_eq!(parse_duration("2s", TIMING), Ok(1200));
+        assert_eq!(parse_duration("1.5 s", TIMING), Ok(900));
+        assert_eq!(parse_duration("250ms", TIMING), Ok(150));
+        assert!(parse_duration("250", TIMING).is_err());
+        assert!(parse_duration("2 hours", TIMING).is_err());
+        assert!(parse_duration("frames", TIMING).is_err());
+        let untimed = Timing {
+            clk_freq: 0,
+            ..TIMING
+        };
+        assert!(parse_duration("2s", untimed).is_err());
+    }
+
+    #[test]
+    fn expands_nested_repeats() {
+        let source = "
+            wait 1 frame # the title screen
+            repeat 2 {
+                repeat 2 { press 4 for 5 cycles }
+                wait 5 cycles
+            }
+            tap 5
+        ";
+        assert_eq!(
+            events(source),
+            [
+                "10 4 Press",
+                "15 4 Release",
+                "16 4 Press",
+                "20 4 Release",
+                "25 4 Press",
+                "30 4 Release",
+                "31 4 Press",
+                "35 4 Release",
+                "40 5 Press",
+                "60 5 Release",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let error = |source| match parse(source, TIMING) {
+            Err(Chip8Error::ScriptError(message)) => message,
+            other => panic!("{source:?} parsed as {other:?}"),
+        };
+        assert_eq!(
+            error("wait 1 frame\nwiat 2 frames"),
+            "line 2: Unknown script keyword: wiat"
+        );
+        assert_eq!(error("press G"), "line 1: Invalid key: G");
+        assert_eq!(error("hold 4"), "line 1: Missing duration");
+        assert_eq!(
+            error("repeat x { tap 4 }"),
+            "line 1: Invalid repeat count: x"
+        );
+        assert_eq!(
+            error("repeat 2\ntap 4"),
+            "line 1: Expected '{' after repeat"
+        );
+        assert_eq!(error("repeat 2 {\ntap 4"), "line 2: Missing '}'");
+        assert_eq!(error("tap 4 }"), "line 1: Unmatched '}'");
+        assert_eq!(
+            error("repeat 18446744073709551615 { }"),
+            "line 1: Repeat runs more than 1000000 statements"
+        );
+        assert_eq!(
+            error("repeat 1000 {\nrepeat 1000 { tap 4 }\n}\ntap 5"),
+            "Script runs more than 1000000 statements"
+        );
+    }
+
+    #[test]
+    fn detects_scripts() {
+        assert!(detect(b"# intro\nwait 2s"));
+        assert!(detect(b"waitt 2s"));
+        assert!(!detect(b"clk,key,kind\n10,4,1"));
+        assert!(!detect(b"\xFF\xFE"));
+        assert!(!detect(b""));
+    }
+}
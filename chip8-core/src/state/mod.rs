@@ -1,50 +1,89 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+};
 
 use crate::{
-    constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    audio::AudioEvent,
+    constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH, NUM_KEYS, NUM_REGISTERS},
     error::Chip8Error,
     input::InputKind,
     keypad::Key,
 };
 
+mod save;
 mod simple;
+pub use save::{SaveState, SAVE_STATE_SIZE};
 pub use simple::SimpleState;
 
 pub type Address = u16;
 pub type Word = u8;
 
+/// Copy of the registers, timers and keypad, published by the CPU after every
+/// cycle for drivers running on other threads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StateSnapshot {
+    pub clk: u64,
+    pub registers: [Word; NUM_REGISTERS],
+    pub index_register: Address,
+    pub program_counter: Address,
+    pub stack_pointer: Word,
+    pub delay_timer: Word,
+    pub sound_timer: Word,
+    pub keypad: [bool; NUM_KEYS],
+    /// Number of draw instructions executed so far
+    pub draws: u64,
+}
+
 pub trait State: Default {
     fn load_rom(&mut self, bytes: &[u8]) -> Result<(), Chip8Error>;
+    /// Returns to the power-on state with the last loaded ROM. The clk, the
+    /// shared pointers and the keypad are kept.
+    fn reset(&mut self) -> Result<(), Chip8Error>;
+    fn save_state(&self) -> Result<SaveState, Chip8Error>;
+    /// Replaces the machine state, including the clk, with `state`.
+    fn load_state(&mut self, state: &SaveState) -> Result<(), Chip8Error>;
 
     fn clk(&self) -> Result<u64, Chip8Error>;
     fn program_counter(&self) -> Address;
     fn delay_timer(&self) -> Word;
-    fn sound_timer(&self) -> Result<Word, Chip8Error>;
+    fn sound_timer(&self) -> Word;
     fn memory(&self, addr: Address) -> Result<Word, Chip8Error>;
     fn register(&self, index: Word) -> Word;
     fn index_register(&self) -> Address;
+    fn stack_pointer(&self) -> Word;
     fn key(&self, index: Word) -> bool;
     fn frame_buffer(&self, y: usize, x: usize) -> Result<bool, Chip8Error>;
 
     fn set_frame_buffer(&self, y: usize, x: usize, bit: bool) -> Result<(), Chip8Error>;
     fn set_program_counter(&mut self, pc: Address);
     fn set_delay_timer(&mut self, value: Word);
-    fn set_sound_timer(&mut self, value: Word) -> Result<(), Chip8Error>;
+    fn set_sound_timer(&mut self, value: Word);
     fn set_index_register(&mut self, addr: Address);
     fn set_register(&mut self, index: Word, value: Word);
     fn set_flag_register(&mut self, flag: bool);
     fn set_memory(&mut self, addr: Address, value: Word) -> Result<(), Chip8Error>;
     fn set_key(&mut self, key: Key, kind: InputKind);
+    /// Last key pressed since the previous call.
+    fn take_key_press(&mut self) -> Option<Key>;
+    fn enqueue_audio(&mut self, clk: u64, event: AudioEvent) -> Result<(), Chip8Error>;
 
     fn clear_framebuffer(&mut self) -> Result<(), Chip8Error>;
     fn push_stack(&mut self, addr: Address);
     fn pop_stack(&mut self);
     fn increment_program_counter(&mut self);
+    /// Moves back to the current instruction, so that it runs again.
+    fn rewind_program_counter(&mut self);
     fn increment_clk(&mut self) -> Result<(), Chip8Error>;
     fn decrement_delay_timer(&mut self);
-    fn decrement_sound_timer(&mut self) -> Result<(), Chip8Error>;
+    fn decrement_sound_timer(&mut self);
+    fn increment_draw_count(&mut self);
+
+    fn snapshot(&self) -> Result<StateSnapshot, Chip8Error>;
+    fn publish_snapshot(&mut self) -> Result<(), Chip8Error>;
 
     fn clk_ptr(&self) -> Arc<RwLock<u64>>;
-    fn sound_timer_ptr(&self) -> Arc<RwLock<Word>>;
+    fn audio_queue_ptr(&self) -> Arc<RwLock<VecDeque<(u64, AudioEvent)>>>;
     fn frame_buffer_ptr(&self) -> Arc<RwLock<[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]>>;
+    fn snapshot_ptr(&self) -> Arc<RwLock<StateSnapshot>>;
 }
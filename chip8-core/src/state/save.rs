@@ -0,0 +1,140 @@
+use super::{Address, Word};
+use crate::{
+    constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH, MEMORY_SIZE, NUM_KEYS, NUM_REGISTERS, STACK_DEPTH},
+    error::Chip8Error,
+};
+
+const FRAME_BUFFER_BYTES: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT / 8;
+
+/// Size of [`SaveState::to_bytes`].
+pub const SAVE_STATE_SIZE: usize =
+    8 + NUM_REGISTERS + MEMORY_SIZE + 2 + 2 + 2 * STACK_DEPTH + 3 + NUM_KEYS + FRAME_BUFFER_BYTES;
+
+/// Everything needed to resume a machine at a given cycle. The random number
+/// generator is not part of it: it is reseeded when the state is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub clk: u64,
+    pub registers: [Word; NUM_REGISTERS],
+    pub memory: Vec<Word>,
+    pub index_register: Address,
+    pub program_counter: Address,
+    pub stack: [Address; STACK_DEPTH],
+    pub stack_pointer: Word,
+    pub delay_timer: Word,
+    pub sound_timer: Word,
+    pub keypad: [bool; NUM_KEYS],
+    pub frame_buffer: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+}
+
+impl SaveState {
+    /// Fixed-size little-endian encoding, with the frame buffer packed 8
+    /// pixels per byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SAVE_STATE_SIZE);
+        bytes.extend(self.clk.to_le_bytes());
+        bytes.extend(self.registers);
+        bytes.extend(&self.memory);
+        bytes.extend(self.index_register.to_le_bytes());
+        bytes.extend(self.program_counter.to_le_bytes());
+        for addr in self.stack {
+            bytes.extend(addr.to_le_bytes());
+        }
+        bytes.extend([self.stack_pointer, self.delay_timer, self.sound_timer]);
+        bytes.extend(self.keypad.map(|key| key as u8));
+        for chunk in self.frame_buffer.as_flattened().chunks(8) {
+            bytes.push(
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, &bit)| acc | (bit as u8) << (7 - i)),
+            );
+        }
+        bytes
+    }
+
+    /// 64-bit FNV-1a hash of the encoded state, cheap enough to take every
+    /// few frames.
+    pub fn checksum(&self) -> u64 {
+        self.to_bytes()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    /// The screen as lines of `#` (on) and `.` (off).
+    pub fn screen_text(&self) -> String {
+        self.frame_buffer
+            .iter()
+            .flat_map(|row| {
+                row.iter()
+                    .map(|&on| if on { '#' } else { '.' })
+                    .chain(['\n'])
+            })
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        if bytes.len() != SAVE_STATE_SIZE {
+            return Err(Chip8Error::MovieError(format!(
+                "Save state is {} bytes, expected {SAVE_STATE_SIZE}",
+                bytes.len()
+            )));
+        }
+
+        let mut reader = Reader(bytes);
+        let clk = u64::from_le_bytes(reader.array());
+        let registers = reader.array();
+        let memory = reader.take(MEMORY_SIZE).to_vec();
+        let index_register = Address::from_le_bytes(reader.array());
+        let program_counter = Address::from_le_bytes(reader.array());
+        let stack = std::array::from_fn(|_| Address::from_le_bytes(reader.array()));
+        let [stack_pointer, delay_timer, sound_timer] = reader.array();
+        let keypad = reader.array::<NUM_KEYS>().map(|key| key != 0);
+        let packed = reader.take(FRAME_BUFFER_BYTES);
+        let frame_buffer = std::array::from_fn(|y| {
+            std::array::from_fn(|x| {
+                let i = y * DISPLAY_WIDTH + x;
+                packed[i / 8] >> (7 - i % 8) & 1 == 1
+            })
+        });
+
+        if stack_pointer as usize > STACK_DEPTH {
+            return Err(Chip8Error::MovieError(format!(
+                "Invalid stack pointer in save state: {stack_pointer}"
+            )));
+        }
+
+        Ok(Self {
+            clk,
+            registers,
+            memory,
+            index_register,
+            program_counter,
+            stack,
+            stack_pointer,
+            delay_timer,
+            sound_timer,
+            keypad,
+            frame_buffer,
+        })
+    }
+}
+
+// Sequential reads from a buffer whose length was checked up front
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        head
+    }
+
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N));
+        array
+    }
+}
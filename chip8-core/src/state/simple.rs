@@ -1,7 +1,11 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+};
 
-use super::{Address, State, Word};
+use super::{Address, SaveState, State, StateSnapshot, Word};
 use crate::{
+    audio::AudioEvent,
     constants::{
         DISPLAY_HEIGHT, DISPLAY_WIDTH, FLAG_REGISTER, FONTSET, FONTSET_START_ADDRESS, MEMORY_SIZE,
         NUM_KEYS, NUM_REGISTERS, OPCODE_SIZE, PROGRAM_START_ADDRESS, STACK_DEPTH,
@@ -24,9 +28,17 @@ pub struct SimpleState {
     pub stack: [Address; STACK_DEPTH],
     pub stack_pointer: Word,
     pub delay_timer: Word,
-    pub sound_timer: Arc<RwLock<Word>>,
+    pub sound_timer: Word,
     pub keypad: [bool; NUM_KEYS],
+    /// Last key pressed, consumed by FX0A
+    pub key_press: Option<Key>,
     pub frame_buffer: Arc<RwLock<[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]>>,
+    /// Sound timer transitions not yet consumed by the audio driver.
+    pub audio_queue: Arc<RwLock<VecDeque<(u64, AudioEvent)>>>,
+    pub draws: u64,
+    pub snapshot: Arc<RwLock<StateSnapshot>>,
+    /// Copy of the loaded ROM, used on reset.
+    pub rom: Vec<Word>,
 }
 
 impl Default for SimpleState {
@@ -45,9 +57,14 @@ impl Default for SimpleState {
             stack: [0; STACK_DEPTH],
             stack_pointer: 0,
             delay_timer: 0,
-            sound_timer: Arc::new(RwLock::new(0)),
+            sound_timer: 0,
             keypad: [false; NUM_KEYS],
+            key_press: None,
             frame_buffer: Arc::new(RwLock::new([[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT])),
+            audio_queue: Arc::new(RwLock::new(VecDeque::new())),
+            draws: 0,
+            snapshot: Arc::new(RwLock::new(StateSnapshot::default())),
+            rom: Vec::new(),
         }
     }
 }
@@ -61,10 +78,66 @@ impl State for SimpleState {
             Err(Chip8Error::RomTooBig(bytes.len()))
         } else {
             self.memory[start..end].copy_from_slice(bytes);
+            self.rom = bytes.to_vec();
             Ok(())
         }
     }
 
+    fn reset(&mut self) -> Result<(), Chip8Error> {
+        let fresh = Self::default();
+        self.registers = fresh.registers;
+        self.memory = fresh.memory;
+        self.index_register = fresh.index_register;
+        self.program_counter = fresh.program_counter;
+        self.stack = fresh.stack;
+        self.stack_pointer = fresh.stack_pointer;
+        self.delay_timer = fresh.delay_timer;
+        self.sound_timer = fresh.sound_timer;
+        self.clear_framebuffer()?;
+
+        let rom = std::mem::take(&mut self.rom);
+        self.load_rom(&rom)
+    }
+
+    fn save_state(&self) -> Result<SaveState, Chip8Error> {
+        Ok(SaveState {
+            clk: self.clk()?,
+            registers: self.registers,
+            memory: self.memory.to_vec(),
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack: self.stack,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keypad: self.keypad,
+            frame_buffer: *self.frame_buffer.checked_read()?,
+        })
+    }
+
+    fn load_state(&mut self, state: &SaveState) -> Result<(), Chip8Error> {
+        if state.memory.len() != MEMORY_SIZE {
+            return Err(Chip8Error::MovieError(format!(
+                "Save state has {} bytes of memory, expected {MEMORY_SIZE}",
+                state.memory.len()
+            )));
+        }
+
+        *self.clk.checked_write()? = state.clk;
+        self.registers = state.registers;
+        self.memory.copy_from_slice(&state.memory);
+        self.index_register = state.index_register;
+        self.program_counter = state.program_counter;
+        self.stack = state.stack;
+        self.stack_pointer = state.stack_pointer;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.keypad = state.keypad;
+        self.key_press = None;
+        *self.frame_buffer.checked_write()? = state.frame_buffer;
+        Ok(())
+    }
+
     fn clk(&self) -> Result<u64, Chip8Error> {
         let clk = *self.clk.checked_read()?;
         Ok(clk)
@@ -74,14 +147,18 @@ impl State for SimpleState {
         self.clk.clone()
     }
 
-    fn sound_timer_ptr(&self) -> Arc<RwLock<Word>> {
-        self.sound_timer.clone()
+    fn audio_queue_ptr(&self) -> Arc<RwLock<VecDeque<(u64, AudioEvent)>>> {
+        self.audio_queue.clone()
     }
 
     fn frame_buffer_ptr(&self) -> Arc<RwLock<[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]>> {
         self.frame_buffer.clone()
     }
 
+    fn snapshot_ptr(&self) -> Arc<RwLock<StateSnapshot>> {
+        self.snapshot.clone()
+    }
+
     fn program_counter(&self) -> Address {
         self.program_counter
     }
@@ -90,9 +167,8 @@ impl State for SimpleState {
         self.delay_timer
     }
 
-    fn sound_timer(&self) -> Result<Word, Chip8Error> {
-        let st = *self.sound_timer.checked_read()?;
-        Ok(st)
+    fn sound_timer(&self) -> Word {
+        self.sound_timer
     }
 
     fn memory(&self, addr: Address) -> Result<Word, Chip8Error> {
@@ -111,6 +187,10 @@ impl State for SimpleState {
         self.index_register
     }
 
+    fn stack_pointer(&self) -> Word {
+        self.stack_pointer
+    }
+
     fn key(&self, index: Word) -> bool {
         self.keypad[index as usize]
     }
@@ -133,9 +213,8 @@ impl State for SimpleState {
         self.delay_timer = value;
     }
 
-    fn set_sound_timer(&mut self, value: Word) -> Result<(), Chip8Error> {
-        *self.sound_timer.checked_write()? = value;
-        Ok(())
+    fn set_sound_timer(&mut self, value: Word) {
+        self.sound_timer = value;
     }
 
     fn set_index_register(&mut self, addr: Address) {
@@ -161,6 +240,18 @@ impl State for SimpleState {
 
     fn set_key(&mut self, key: Key, kind: InputKind) {
         self.keypad[key as usize] = kind == InputKind::Press;
+        if kind == InputKind::Press {
+            self.key_press = Some(key);
+        }
+    }
+
+    fn take_key_press(&mut self) -> Option<Key> {
+        self.key_press.take()
+    }
+
+    fn enqueue_audio(&mut self, clk: u64, event: AudioEvent) -> Result<(), Chip8Error> {
+        (*self.audio_queue.checked_write()?).push_back((clk, event));
+        Ok(())
     }
 
     fn clear_framebuffer(&mut self) -> Result<(), Chip8Error> {
@@ -180,7 +271,12 @@ impl State for SimpleState {
     }
 
     fn increment_program_counter(&mut self) {
-        self.program_counter += OPCODE_SIZE;
+        // Only past the end of memory, where skipping faults can take it
+        self.program_counter = self.program_counter.wrapping_add(OPCODE_SIZE);
+    }
+
+    fn rewind_program_counter(&mut self) {
+        self.program_counter -= OPCODE_SIZE;
     }
 
     fn increment_clk(&mut self) -> Result<(), Chip8Error> {
@@ -192,8 +288,31 @@ impl State for SimpleState {
         self.delay_timer -= 1;
     }
 
-    fn decrement_sound_timer(&mut self) -> Result<(), Chip8Error> {
-        *self.sound_timer.checked_write()? -= 1;
+    fn decrement_sound_timer(&mut self) {
+        self.sound_timer -= 1;
+    }
+
+    fn increment_draw_count(&mut self) {
+        self.draws += 1;
+    }
+
+    fn snapshot(&self) -> Result<StateSnapshot, Chip8Error> {
+        Ok(StateSnapshot {
+            clk: self.clk()?,
+            registers: self.registers,
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keypad: self.keypad,
+            draws: self.draws,
+        })
+    }
+
+    fn publish_snapshot(&mut self) -> Result<(), Chip8Error> {
+        let snapshot = self.snapshot()?;
+        *self.snapshot.checked_write()? = snapshot;
         Ok(())
     }
 }
@@ -1,27 +1,23 @@
-use std::{
-    sync::{Arc, RwLock},
-    thread::sleep,
-    time::{Duration, SystemTime},
-};
+use std::time::{Duration, SystemTime};
 
-use crate::{
-    error::Chip8Error,
-    rwlock::{CheckedRead, CheckedWrite},
-};
+use crate::{cancel::CancellationToken, error::Chip8Error};
 
-fn run_loop_inner(
-    status: Arc<RwLock<Result<(), Chip8Error>>>,
-    frequency: u64,
-    mut fn_tick: impl FnMut(Duration) -> Result<(), Chip8Error>,
-) -> Result<(), Chip8Error> {
-    let interval = if frequency > 0 {
+fn interval(frequency: u64) -> Duration {
+    if frequency > 0 {
         Duration::from_secs_f64(1.0 / frequency as f64)
     } else {
         Duration::ZERO
-    };
+    }
+}
 
+fn run_loop_inner(
+    token: &CancellationToken,
+    mut frequency: impl FnMut() -> Result<u64, Chip8Error>,
+    mut fn_tick: impl FnMut(Duration) -> Result<(), Chip8Error>,
+) -> Result<(), Chip8Error> {
     let mut prev_time = SystemTime::now();
-    while status.checked_read()?.is_ok() {
+    while !token.is_cancelled() {
+        let interval = interval(frequency()?);
         let curr_time = SystemTime::now();
         let elapsed = curr_time.duration_since(prev_time).unwrap_or_default();
 
@@ -29,7 +25,8 @@ fn run_loop_inner(
             fn_tick(elapsed)?;
             prev_time = curr_time;
         } else {
-            sleep(
+            // Parked rather than asleep, so that cancelling wakes it up
+            token.park(
                 interval
                     .checked_sub(elapsed)
                     .unwrap_or_default()
@@ -41,14 +38,24 @@ fn run_loop_inner(
     Ok(())
 }
 
+/// Calls `fn_tick` `frequency` times per second, with the time since the last
+/// call, until `token` is cancelled. An error cancels it.
 pub fn run_loop(
-    status: Arc<RwLock<Result<(), Chip8Error>>>,
+    token: &CancellationToken,
     frequency: u64,
     fn_tick: impl FnMut(Duration) -> Result<(), Chip8Error>,
 ) {
-    let res = run_loop_inner(status.clone(), frequency, fn_tick);
+    run_loop_dynamic(token, move || Ok(frequency), fn_tick);
+}
 
-    if let Err(err) = res {
-        *status.checked_write().unwrap() = Err(err);
+/// Like [`run_loop`], but the frequency is queried before every iteration so
+/// that it can change while running. A frequency of 0 runs unthrottled.
+pub fn run_loop_dynamic(
+    token: &CancellationToken,
+    frequency: impl FnMut() -> Result<u64, Chip8Error>,
+    fn_tick: impl FnMut(Duration) -> Result<(), Chip8Error>,
+) {
+    if let Err(err) = run_loop_inner(token, frequency, fn_tick) {
+        token.cancel(err);
     }
 }
@@ -1,34 +1,275 @@
-use clap::Parser;
+use chip8_core::{
+    constants::MEMORY_SIZE,
+    control::StopConditions,
+    fault::{FaultAction, FaultKind, FaultPolicy},
+    filter::FilterMode,
+    movie::{MovieEncoding, Timebase},
+};
+use clap::{Parser, Subcommand};
 use ratatui::style::Color;
 use std::path::PathBuf;
 
+use crate::{
+    config::Settings,
+    headless::ReportFormat,
+    keymap::{KeyConfig, KeymapPreset},
+    render::{PalettePreset, RenderMode},
+};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(
+    after_help = "Exit status: 0 on quit, 3 when halted, 4 when waiting for input, \
+                  5 at the cycle limit, 6 at --until-pc, 70 on a fault, 1 on other errors"
+)]
 pub struct CmdArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(required = true, value_parser)]
-    pub rom: PathBuf,
+    pub rom: Option<PathBuf>,
+
+    /// Config file, by default ~/.config/chip8/config.toml
+    #[arg(long)]
+    pub config: Option<PathBuf>,
 
-    #[arg(long = "clock-frequency", default_value_t = 560)]
-    pub clk_freq: u64,
-    #[arg(long, default_value_t = 60)]
-    pub refresh_rate: u64,
+    /// Defaults to 560
+    #[arg(long = "clock-frequency")]
+    pub clk_freq: Option<u64>,
+    /// Defaults to 60
+    #[arg(long)]
+    pub refresh_rate: Option<u64>,
+    /// Cycles per tick of the 60Hz timers. Defaults to 8
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub ticks_per_timer: Option<u64>,
 
+    /// Run without a terminal, replaying only the --input movie or script,
+    /// and print a report once a stop condition is met
     #[arg(long, default_value_t = false)]
     pub headless: bool,
+    /// Format of the headless report
+    #[arg(long, value_enum, default_value_t, requires = "headless")]
+    pub report: ReportFormat,
+
+    /// Stop after this many cycles
+    #[arg(long)]
+    pub max_cycles: Option<u64>,
+    /// Stop after this many frames
+    #[arg(long)]
+    pub max_frames: Option<u64>,
+    /// Stop when the program counter reaches this address, e.g. 0x2A0
+    #[arg(long, value_parser = parse_address)]
+    pub until_pc: Option<u16>,
+    /// Stop when the program jumps to itself, or loops with no way out. Headless
+    /// runs also stop when it waits for a key that no input is left to press
+    #[arg(long, default_value_t = false)]
+    pub until_halt: bool,
+
+    /// What to do when an instruction fails: abort, skip (and log), nop (skip
+    /// silently) or pause. Either for all faults, or as KIND=ACTION with KIND one of
+    /// unimplemented-opcode and memory-out-of-bounds. Can be repeated
+    #[arg(long = "on-fault", value_name = "[KIND=]ACTION", value_parser = parse_fault_rule)]
+    pub fault_rules: Vec<(Option<FaultKind>, FaultAction)>,
 
     #[arg(long)]
     pub random_seed: Option<u64>,
 
+    /// Movie to replay and record into. Older CSV input logs are converted,
+    /// input scripts are replayed without recording
     #[arg(long = "input")]
     pub input_file: Option<PathBuf>,
 
+    /// Start a new movie instead of replaying and appending to the existing one
     #[arg(long, default_value_t = false, requires = "input_file")]
     pub overwrite: bool,
+    /// Replay the movie without recording, ignoring live input until taken over
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "input_file",
+        conflicts_with = "overwrite"
+    )]
+    pub read_only: bool,
+    /// Run unthrottled until the given clk, e.g. to skip ahead in a movie
+    #[arg(long)]
+    pub fast_forward: Option<u64>,
+    /// Record a state checksum every N frames, 0 to disable [default: 60].
+    /// Frame-timed movies have none
+    #[arg(long, requires = "input_file")]
+    pub checkpoint_frames: Option<u64>,
+    /// Write new movies in the compact binary encoding instead of text
+    #[arg(long, default_value_t = false, requires = "input_file")]
+    pub binary: bool,
+    /// Time the events of new movies in frames rather than cycles, so that
+    /// they replay at any ticks per timer
+    #[arg(long, default_value_t = false, requires = "input_file")]
+    pub frames: bool,
+    /// Author stored in new movies
+    #[arg(long, requires = "input_file")]
+    pub author: Option<String>,
+    /// Comment stored in new movies
+    #[arg(long, requires = "input_file")]
+    pub comment: Option<String>,
+
+    #[arg(long = "midi")]
+    pub midi_file: Option<PathBuf>,
+
+    /// Color scheme, overridden by individual colors
+    #[arg(long, value_enum, conflicts_with = "headless")]
+    pub palette: Option<PalettePreset>,
+    #[arg(long = "background", conflicts_with = "headless")]
+    pub bg_color: Option<Color>,
+    #[arg(long = "foreground", conflicts_with = "headless")]
+    pub fg_color: Option<Color>,
+    #[arg(long = "border", conflicts_with = "headless")]
+    pub border_color: Option<Color>,
+    /// Defaults to auto
+    #[arg(long, value_enum, conflicts_with = "headless")]
+    pub render_mode: Option<RenderMode>,
+
+    /// Show a pixel if it was lit in any of the last N frames
+    #[arg(long, conflicts_with_all = ["headless", "phosphor_frames"])]
+    pub blend_frames: Option<usize>,
+    /// Fade pixels out over N frames after they turn off
+    #[arg(long, conflicts_with = "headless")]
+    pub phosphor_frames: Option<usize>,
+
+    /// Key layout, overriding the preset in the config file
+    #[arg(long, value_enum, conflicts_with = "headless")]
+    pub keymap: Option<KeymapPreset>,
+
+    /// Milliseconds a key stays pressed after its last key press, used when
+    /// the terminal can't report key releases. Defaults to 200
+    #[arg(long, conflicts_with = "headless")]
+    pub key_hold: Option<u64>,
+
+    /// Show registers, timers, keypad and frame statistics beside the game
+    #[arg(long, default_value_t = false, conflicts_with = "headless")]
+    pub status_panel: bool,
+
+    /// Show a keypad that can be clicked with the mouse
+    #[arg(long, default_value_t = false, conflicts_with = "headless")]
+    pub keypad: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Replay a movie headless at full speed and report the first checkpoint
+    /// where the state differs from the recording
+    Verify { movie: PathBuf, rom: PathBuf },
+    /// Run a scenario headless and check its expectations, exiting with an
+    /// error if any fails
+    Scenario { scenario: PathBuf, rom: PathBuf },
+    /// Convert a movie between cycle and frame timing, or to another number
+    /// of ticks per timer
+    Retime {
+        movie: PathBuf,
+        output: PathBuf,
+        /// Defaults to the movie's
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        ticks_per_timer: Option<u64>,
+        /// Time the events in frames rather than cycles
+        #[arg(long, default_value_t = false)]
+        frames: bool,
+        /// Write the binary encoding, by default the same as the movie's
+        #[arg(long, default_value_t = false)]
+        binary: bool,
+        /// Write the text encoding
+        #[arg(long, default_value_t = false, conflicts_with = "binary")]
+        text: bool,
+    },
+}
+
+impl CmdArgs {
+    /// Settings given on the command line, which take precedence over the
+    /// config file.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            clock_frequency: self.clk_freq,
+            refresh_rate: self.refresh_rate,
+            ticks_per_timer: self.ticks_per_timer,
+            palette: self.palette,
+            foreground: self.fg_color,
+            background: self.bg_color,
+            border: self.border_color,
+            render_mode: self.render_mode,
+            key_hold: self.key_hold,
+            keys: KeyConfig {
+                preset: self.keymap,
+                ..KeyConfig::default()
+            },
+        }
+    }
+
+    pub fn movie_encoding(&self) -> MovieEncoding {
+        if self.binary {
+            MovieEncoding::Binary
+        } else {
+            MovieEncoding::Text
+        }
+    }
+
+    pub fn timebase(&self) -> Timebase {
+        if self.frames {
+            Timebase::Frames
+        } else {
+            Timebase::Cycles
+        }
+    }
+
+    /// Stop conditions, with frames counted at `ticks_per_timer`.
+    pub fn stop_conditions(&self, ticks_per_timer: u64) -> StopConditions {
+        // Too many cycles to count are as good as no limit
+        let max_frames = self
+            .max_frames
+            .and_then(|frames| frames.checked_mul(ticks_per_timer));
+        StopConditions {
+            max_cycles: match (self.max_cycles, max_frames) {
+                (Some(cycles), Some(frames)) => Some(cycles.min(frames)),
+                (cycles, frames) => cycles.or(frames),
+            },
+            until_pc: self.until_pc,
+            until_halt: self.until_halt,
+            until_input: self.until_halt && self.headless,
+        }
+    }
+
+    /// Later rules override earlier ones.
+    pub fn fault_policy(&self) -> FaultPolicy {
+        let mut policy = FaultPolicy::default();
+        for &(kind, action) in &self.fault_rules {
+            match kind {
+                Some(kind) => policy.set(kind, action),
+                None => policy = FaultPolicy::all(action),
+            }
+        }
+        policy
+    }
+
+    pub fn filter_mode(&self) -> FilterMode {
+        match (self.blend_frames, self.phosphor_frames) {
+            (Some(frames), _) => FilterMode::Blend(frames),
+            (_, Some(frames)) => FilterMode::Phosphor(frames),
+            _ => FilterMode::None,
+        }
+    }
+}
+
+fn parse_fault_rule(s: &str) -> Result<(Option<FaultKind>, FaultAction), String> {
+    match s.split_once('=') {
+        Some((kind, action)) => Ok((Some(kind.parse()?), action.parse()?)),
+        None => Ok((None, s.parse()?)),
+    }
+}
 
-    #[arg(long = "background", default_value_t = Color::Black, conflicts_with="headless")]
-    pub bg_color: Color,
-    #[arg(long = "foreground", default_value_t = Color::White, conflicts_with="headless")]
-    pub fg_color: Color,
-    #[arg(long = "border", default_value_t = Color::White, conflicts_with="headless")]
-    pub border_color: Color,
+fn parse_address(s: &str) -> Result<u16, String> {
+    let address = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    address
+        .ok()
+        .filter(|&address| (address as usize) < MEMORY_SIZE)
+        .ok_or_else(|| format!("invalid address: {s}"))
 }
@@ -0,0 +1,219 @@
+use chip8_core::constants::TICKS_PER_TIMER;
+use eyre::{bail, Result, WrapErr};
+use ratatui::style::Color;
+use serde::{de::IgnoredAny, Deserialize};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    keymap::{KeyConfig, Keymap},
+    render::{Palette, PalettePreset, RenderMode},
+};
+
+pub const DEFAULT_CLOCK_FREQUENCY: u64 = 560;
+pub const DEFAULT_REFRESH_RATE: u64 = 60;
+pub const DEFAULT_KEY_HOLD_MS: u64 = 200;
+
+/// Contents of the config file. Top-level settings apply to every ROM and are
+/// overridden by the section of the ROM being run, if any. There is no
+/// platform or quirks setting, as the core only emulates the original CHIP-8.
+///
+/// ```toml
+/// clock-frequency = 700
+/// ticks-per-timer = 12
+/// palette = "amber"
+///
+/// [keys]
+/// preset = "azerty"
+/// keypad = { A = ["w"], F = ["v", "b"] }
+/// controls = { pause = ["p"] }
+/// autofire = { g = { key = "5", frames = 3 } }
+///
+/// # Keyed by the SHA-1 of the ROM
+/// [roms.0123456789abcdef0123456789abcdef01234567]
+/// clock-frequency = 1000
+/// keys = { preset = "numpad" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub global: Settings,
+    pub roms: HashMap<String, Settings>,
+    // What the flattened settings leave over, as `deny_unknown_fields` doesn't
+    // work along with `flatten`
+    #[serde(flatten)]
+    unknown: HashMap<String, IgnoredAny>,
+}
+
+/// Settings that can be given on the command line, per ROM or globally, in
+/// decreasing order of precedence. Explicit colors win over the palette.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub clock_frequency: Option<u64>,
+    pub refresh_rate: Option<u64>,
+    /// Cycles per timer tick
+    pub ticks_per_timer: Option<u64>,
+    pub palette: Option<PalettePreset>,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub border: Option<Color>,
+    pub render_mode: Option<RenderMode>,
+    /// Milliseconds a key stays down after its last press on terminals that
+    /// don't report releases
+    pub key_hold: Option<u64>,
+    pub keys: KeyConfig,
+}
+
+impl Settings {
+    fn foreground(&self) -> Option<Color> {
+        self.foreground
+            .or(self.palette.map(|preset| preset.palette().fg))
+    }
+
+    fn background(&self) -> Option<Color> {
+        self.background
+            .or(self.palette.map(|preset| preset.palette().bg))
+    }
+
+    // Presets draw the border in the foreground color
+    fn border(&self) -> Option<Color> {
+        self.border
+            .or(self.palette.map(|preset| preset.palette().fg))
+    }
+}
+
+/// Settings after merging every source and filling in the defaults.
+pub struct Resolved {
+    pub clk_freq: u64,
+    pub refresh_rate: u64,
+    pub ticks_per_timer: u64,
+    pub palette: Palette,
+    pub border_color: Color,
+    pub render_mode: RenderMode,
+    pub key_hold: Duration,
+    pub keymap: Keymap,
+}
+
+impl Config {
+    /// Reads `path`, or the default location if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path().filter(|path| path.exists()) {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read config {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .wrap_err_with(|| format!("Invalid config {}", path.display()))?;
+        let mut unknown = config.unknown.keys().collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            unknown.sort();
+            bail!(
+                "Invalid config {}: unknown field(s) {}",
+                path.display(),
+                unknown
+                    .iter()
+                    .map(|key| format!("`{key}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        config
+            .global
+            .keys
+            .check()
+            .wrap_err_with(|| format!("Invalid config {}", path.display()))?;
+        for (hash, settings) in &config.roms {
+            settings
+                .keys
+                .check()
+                .wrap_err_with(|| format!("Invalid config {}: ROM {hash}", path.display()))?;
+        }
+        Ok(config)
+    }
+
+    /// Merges `cli` with the settings for `rom` and the global ones.
+    pub fn resolve(&self, rom: &[u8], cli: &Settings) -> Result<Resolved> {
+        let rom = self.roms.get(&rom_hash(rom));
+        let sources = [Some(cli), rom, Some(&self.global)]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let palette = Palette {
+            fg: sources
+                .iter()
+                .find_map(|s| s.foreground())
+                .unwrap_or(Color::White),
+            bg: sources
+                .iter()
+                .find_map(|s| s.background())
+                .unwrap_or(Color::Black),
+        };
+
+        // Key overrides are layered from the least specific source up
+        let preset = sources.iter().find_map(|s| s.keys.preset);
+        let mut keymap = Keymap::preset(preset.unwrap_or_default());
+        for settings in sources.iter().rev() {
+            keymap.apply(&settings.keys)?;
+        }
+
+        Ok(Resolved {
+            clk_freq: sources
+                .iter()
+                .find_map(|s| s.clock_frequency)
+                .unwrap_or(DEFAULT_CLOCK_FREQUENCY),
+            refresh_rate: sources
+                .iter()
+                .find_map(|s| s.refresh_rate)
+                .unwrap_or(DEFAULT_REFRESH_RATE),
+            ticks_per_timer: sources
+                .iter()
+                .find_map(|s| s.ticks_per_timer)
+                .unwrap_or(TICKS_PER_TIMER)
+                .max(1),
+            palette,
+            border_color: sources
+                .iter()
+                .find_map(|s| s.border())
+                .unwrap_or(Color::White),
+            render_mode: sources
+                .iter()
+                .find_map(|s| s.render_mode)
+                .unwrap_or(RenderMode::Auto),
+            key_hold: Duration::from_millis(
+                sources
+                    .iter()
+                    .find_map(|s| s.key_hold)
+                    .unwrap_or(DEFAULT_KEY_HOLD_MS),
+            ),
+            keymap,
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chip8/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("chip8").join("config.toml"))
+}
+
+/// Lowercase hex SHA-1 of the ROM, used to key per-ROM sections.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
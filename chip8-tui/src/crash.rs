@@ -0,0 +1,80 @@
+use chip8_core::{
+    cpu::Cpu,
+    crash::CrashReport,
+    fault::FaultAction,
+    movie::{Movie, MovieEncoding, MovieHeader},
+    Chip8, ExitReason,
+};
+use eyre::{Result, WrapErr};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Leaves a crash report behind if the run ended in a fault. `header`
+/// describes how the machine was set up.
+pub fn report<C: Cpu>(
+    chip8: &mut Chip8<C>,
+    reason: &ExitReason,
+    rom_path: &Path,
+    header: MovieHeader,
+) -> Result<()> {
+    if let Some(report) = chip8.crash_report(reason)? {
+        let (report_path, movie_path) = write(&report, rom_path, header)?;
+        eprintln!(
+            "Crash report written to {}, reproduce with: chip8 {} --input {} --read-only",
+            report_path.display(),
+            rom_path.display(),
+            movie_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Logs the faults that the run went on after, see `--on-fault`.
+pub fn report_suppressed<C: Cpu>(chip8: &Chip8<C>) -> Result<()> {
+    let trace = chip8.trace()?;
+    if trace.suppressed == 0 {
+        return Ok(());
+    }
+    for fault in &trace.faults {
+        if fault.action == FaultAction::Skip {
+            eprintln!("warning: {fault}");
+        }
+    }
+    let logged = trace.faults.len() as u64;
+    if trace.suppressed > logged {
+        eprintln!(
+            "warning: {} earlier faults not shown",
+            trace.suppressed - logged
+        );
+    }
+    eprintln!("Suppressed {} faults", trace.suppressed);
+    Ok(())
+}
+
+/// Writes the report for a fault to `<rom>-crash-<clk>.txt` in the current
+/// directory, next to a movie of the same name that starts from the faulting
+/// state. Returns both paths.
+fn write(report: &CrashReport, rom_path: &Path, header: MovieHeader) -> Result<(PathBuf, PathBuf)> {
+    let stem = rom_path
+        .file_stem()
+        .map_or("chip8".into(), |stem| stem.to_string_lossy());
+    let name = format!("{stem}-crash-{}", report.state.clk);
+    let report_path = PathBuf::from(format!("{name}.txt"));
+    let movie_path = PathBuf::from(format!("{name}.c8m"));
+
+    fs::write(&report_path, report.to_string())
+        .wrap_err_with(|| format!("Failed to write {}", report_path.display()))?;
+
+    let mut movie = Movie::new(MovieHeader {
+        comment: Some(format!("Reproduces: {}", report.error)),
+        rng_position: report.rng_position,
+        ..header
+    });
+    movie.start = Some(report.state.clone());
+    fs::write(&movie_path, movie.encode(MovieEncoding::Text))
+        .wrap_err_with(|| format!("Failed to write {}", movie_path.display()))?;
+
+    Ok((report_path, movie_path))
+}
@@ -11,11 +11,15 @@ impl AudioDriver for TerminalAudio {
         FREQUENCY
     }
 
-    fn beep(&mut self) -> Result<(), Chip8Error> {
+    fn sound_on(&mut self, _clk: u64) -> Result<(), Chip8Error> {
         let mut stdout = stdout();
         write!(stdout, "\x07").map_err(|e| Chip8Error::AudioError(e.to_string()))?;
         stdout
             .flush()
             .map_err(|e| Chip8Error::AudioError(e.to_string()))
     }
+
+    fn sound_off(&mut self, _clk: u64) -> Result<(), Chip8Error> {
+        Ok(())
+    }
 }
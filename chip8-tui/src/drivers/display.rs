@@ -1,41 +1,180 @@
 use chip8_core::{
     constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH},
-    drivers::DisplayDriver,
+    drivers::{DisplayDriver, DisplayFrame},
     error::Chip8Error,
+    filter::{FilterMode, FrameFilter},
+    idle::Activity,
+    rwlock::{CheckedRead, CheckedWrite},
 };
+use crossterm::{cursor::MoveTo, queue, terminal::window_size};
 use ratatui::{
     backend::Backend,
-    layout::Rect,
+    layout::{Constraint, Flex, Layout, Rect},
     style::{Color, Stylize},
-    widgets::{Block, Paragraph},
+    widgets::{Block, Clear, Paragraph},
     Terminal,
 };
+use std::io::{stdout, Write};
+
+use crate::{
+    graphics::{GraphicsProtocol, Image},
+    help::{help_overlay, HELP_WIDTH},
+    keymap::Keymap,
+    keypad::{keypad, keypad_buttons, KEYPAD_HEIGHT, KEYPAD_WIDTH},
+    panel::{status_panel, PANEL_HEIGHT, PANEL_WIDTH},
+    render::{centered, min_terminal_size, Palette, RenderMode, Renderer},
+    ui::SharedUiState,
+};
 
-// TODO: Builder pattern
 pub struct TerminalDisplay<B: Backend> {
     terminal: Terminal<B>,
     refresh_rate: u64,
-    bg_color: Color,
-    fg_color: Color,
+    palette: Palette,
     border_color: Color,
+    render_mode: RenderMode,
+    graphics: Option<GraphicsProtocol>,
+    filter: FrameFilter,
+    status_panel: bool,
+    keypad: bool,
+    keymap: Keymap,
+    ui: SharedUiState,
+    // Terminal size and frame of the last bitmap sent
+    last_image: Option<(Rect, [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT])>,
 }
 
 impl<B: Backend> TerminalDisplay<B> {
-    pub fn new(
-        terminal: Terminal<B>,
-        refresh_rate: u64,
-        bg_color: Color,
-        fg_color: Color,
-        border_color: Color,
-    ) -> Self {
+    pub fn new(terminal: Terminal<B>, refresh_rate: u64) -> Self {
         Self {
             terminal,
             refresh_rate,
-            bg_color,
-            fg_color,
-            border_color,
+            palette: Palette {
+                fg: Color::White,
+                bg: Color::Black,
+            },
+            border_color: Color::White,
+            render_mode: RenderMode::Auto,
+            graphics: None,
+            filter: FrameFilter::new(FilterMode::None),
+            status_panel: false,
+            keypad: false,
+            keymap: Keymap::default(),
+            ui: SharedUiState::default(),
+            last_image: None,
+        }
+    }
+
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn with_border_color(mut self, border_color: Color) -> Self {
+        self.border_color = border_color;
+        self
+    }
+
+    /// `graphics` is the bitmap protocol detected by `setup_terminal`, used in
+    /// [`RenderMode::Auto`].
+    pub fn with_render_mode(
+        mut self,
+        render_mode: RenderMode,
+        graphics: Option<GraphicsProtocol>,
+    ) -> Self {
+        self.render_mode = render_mode;
+        self.graphics = graphics;
+        self
+    }
+
+    pub fn with_filter(mut self, filter_mode: FilterMode) -> Self {
+        self.filter = FrameFilter::new(filter_mode);
+        self
+    }
+
+    pub fn with_status_panel(mut self, status_panel: bool) -> Self {
+        self.status_panel = status_panel;
+        self
+    }
+
+    /// Clicks on the keypad reach the input driver through the UI state.
+    pub fn with_keypad(mut self, keypad: bool) -> Self {
+        self.keypad = keypad;
+        self
+    }
+
+    /// Bindings listed in the help overlay.
+    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    /// State shared with the input driver, which toggles the help overlay and
+    /// receives clicks on the keypad.
+    pub fn with_ui_state(mut self, ui: SharedUiState) -> Self {
+        self.ui = ui;
+        self
+    }
+
+    fn protocol(&self) -> Option<GraphicsProtocol> {
+        match self.render_mode {
+            RenderMode::Auto => self.graphics,
+            RenderMode::Kitty => Some(GraphicsProtocol::Kitty),
+            RenderMode::Sixel => Some(GraphicsProtocol::Sixel),
+            _ => None,
         }
     }
+
+    fn draw_image(
+        &mut self,
+        protocol: GraphicsProtocol,
+        size: Rect,
+        area: Rect,
+        scale: usize,
+        intensities: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    ) -> Result<(), Chip8Error> {
+        // Bitmaps are only re-sent when something changed
+        if self.last_image == Some((size, intensities)) {
+            return Ok(());
+        }
+
+        let palette = self.palette;
+        let colors = intensities.map(|row| row.map(|pixel| palette.shade_rgb(pixel)));
+        let image = Image::new(&colors);
+        let encoded = protocol.encode(&image, scale, (area.width, area.height));
+
+        let mut stdout = stdout();
+        queue!(stdout, MoveTo(area.x, area.y))
+            .and_then(|_| write!(stdout, "{encoded}"))
+            .and_then(|_| stdout.flush())
+            .map_err(|e| Chip8Error::DisplayError(e.to_string()))?;
+        self.last_image = Some((size, intensities));
+
+        Ok(())
+    }
+
+    fn clear_image(&mut self) -> Result<(), Chip8Error> {
+        let Some(protocol) = self.protocol() else {
+            return Ok(());
+        };
+        if self.last_image.take().is_none() {
+            return Ok(());
+        }
+
+        let mut stdout = stdout();
+        write!(stdout, "{}", protocol.clear())
+            .and_then(|_| stdout.flush())
+            .map_err(|e| Chip8Error::DisplayError(e.to_string()))
+    }
+}
+
+// Size of a terminal cell in pixels, if the terminal reports it
+fn cell_size() -> Option<(usize, usize)> {
+    let size = window_size().ok()?;
+    (size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0).then(|| {
+        (
+            (size.width / size.columns) as usize,
+            (size.height / size.rows) as usize,
+        )
+    })
 }
 
 impl<B: Backend + Send> DisplayDriver for TerminalDisplay<B> {
@@ -43,45 +182,139 @@ impl<B: Backend + Send> DisplayDriver for TerminalDisplay<B> {
         self.refresh_rate
     }
 
-    fn draw(
-        &mut self,
-        frame_buffer: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-        cpu_freq: Option<u64>,
-    ) -> Result<(), Chip8Error> {
-        let frame_str = frame_buffer
-            .iter()
-            .map(|row| {
-                row.iter()
-                    .map(|&pixel| if pixel { "██" } else { "  " })
-                    .collect::<String>()
-            })
-            .collect::<Vec<String>>()
-            .join("\n");
-
+    fn draw(&mut self, frame: &DisplayFrame) -> Result<(), Chip8Error> {
+        let mode = if frame.control.paused_on_fault.is_some() {
+            " PAUSED ON FAULT"
+        } else if frame.control.paused {
+            " PAUSED"
+        } else if frame.control.fast_forward > 0 {
+            " FAST-FORWARD"
+        } else if frame.control.activity == Activity::Halted {
+            " HALTED"
+        } else if frame.control.activity == Activity::WaitingForKey {
+            " WAITING FOR KEY"
+        } else if frame.control.turbo {
+            " TURBO"
+        } else {
+            ""
+        };
+        let (show_help, playback_end, read_only) = {
+            let ui = self.ui.checked_read()?;
+            (ui.show_help, ui.playback_end, ui.read_only)
+        };
+        let clk = frame.state.clk;
+        let playback = match playback_end {
+            Some(end) if clk < end => format!(" PLAYBACK {clk}/{end} ({}%)", clk * 100 / end),
+            Some(_) if read_only => " PLAYBACK END".to_string(),
+            _ => String::new(),
+        };
         let block = Block::bordered()
-            .title(format!(
-                "CHIP-8 {}",
-                cpu_freq.map_or("".to_string(), |f| format!("{f}Hz")),
-            ))
+            .title(format!("CHIP-8 {}Hz{mode}{playback}", frame.stats.cpu_freq))
             .fg(self.border_color);
-        let area = Rect::new(
-            0,
-            0,
-            2 * DISPLAY_WIDTH as u16 + 2,
-            DISPLAY_HEIGHT as u16 + 2,
-        );
+        let pixels = (DISPLAY_WIDTH, DISPLAY_HEIGHT);
+        let intensities = self.filter.apply(&frame.frame_buffer);
+        let palette = self.palette;
+
+        // Bitmaps would cover the overlay, so the game is drawn as text instead
+        let graphics = if show_help {
+            self.clear_image()?;
+            None
+        } else {
+            self.protocol().zip(cell_size())
+        };
+        let (min_width, min_height) = min_terminal_size(pixels);
 
+        let mut image = None;
+        let mut buttons = vec![];
         self.terminal
-            .draw(|frame| {
-                frame.render_widget(
-                    Paragraph::new(frame_str)
-                        .bg(self.bg_color)
-                        .fg(self.fg_color)
+            .draw(|f| {
+                // Re-evaluated every frame so that resizes are picked up
+                let size = f.size();
+
+                // Side panels are dropped when there is no room left for the game
+                let mut view = size;
+                let mut keypad_area = None;
+                if (self.status_panel || self.keypad) && size.width >= min_width + PANEL_WIDTH {
+                    let [game, side] =
+                        Layout::horizontal([Constraint::Min(0), Constraint::Length(PANEL_WIDTH)])
+                            .areas(size);
+                    view = game;
+
+                    let show_keypad = self.keypad
+                        && (!self.status_panel || side.height >= PANEL_HEIGHT + KEYPAD_HEIGHT);
+                    if self.status_panel && show_keypad {
+                        let [panel, keypad] = Layout::vertical([
+                            Constraint::Length(PANEL_HEIGHT),
+                            Constraint::Length(KEYPAD_HEIGHT),
+                        ])
+                        .flex(Flex::Center)
+                        .areas(side);
+                        f.render_widget(status_panel(frame, self.border_color), panel);
+                        keypad_area = Some(keypad);
+                    } else if self.status_panel {
+                        let panel = centered(side, PANEL_WIDTH, PANEL_HEIGHT);
+                        f.render_widget(status_panel(frame, self.border_color), panel);
+                    } else {
+                        keypad_area = Some(centered(side, KEYPAD_WIDTH, KEYPAD_HEIGHT));
+                    }
+                } else if self.keypad && size.height >= min_height + KEYPAD_HEIGHT {
+                    let [game, bottom] =
+                        Layout::vertical([Constraint::Min(0), Constraint::Length(KEYPAD_HEIGHT)])
+                            .areas(size);
+                    view = game;
+                    keypad_area = Some(centered(bottom, KEYPAD_WIDTH, KEYPAD_HEIGHT));
+                }
+
+                if let Some(area) = keypad_area {
+                    f.render_widget(keypad(&frame.state, self.border_color), area);
+                    buttons = keypad_buttons(area);
+                }
+
+                if let Some((protocol, (cell_width, cell_height))) = graphics {
+                    let width = view.width.saturating_sub(2) as usize * cell_width;
+                    let height = view.height.saturating_sub(2) as usize * cell_height;
+                    let scale = (width / pixels.0).min(height / pixels.1).max(1);
+                    let cells = (
+                        (pixels.0 * scale).div_ceil(cell_width) as u16,
+                        (pixels.1 * scale).div_ceil(cell_height) as u16,
+                    );
+                    let area = centered(view, cells.0 + 2, cells.1 + 2);
+
+                    image = Some((protocol, size, block.inner(area), scale));
+                    f.render_widget(block.bg(self.palette.bg), area);
+                    return;
+                }
+
+                let renderer = Renderer::select(
+                    self.render_mode,
+                    view.width.saturating_sub(2),
+                    view.height.saturating_sub(2),
+                    pixels,
+                );
+                let (width, height) = renderer.size(pixels);
+                let area = centered(view, width + 2, height + 2);
+
+                f.render_widget(
+                    Paragraph::new(renderer.render(&intensities, &palette))
+                        .bg(self.palette.bg)
+                        .fg(self.palette.fg)
                         .block(block),
                     area,
                 );
+
+                if show_help {
+                    let (overlay, height) = help_overlay(&self.keymap, self.border_color);
+                    let area = centered(size, HELP_WIDTH, height);
+                    f.render_widget(Clear, area);
+                    f.render_widget(overlay, area);
+                }
             })
             .map_err(|e| Chip8Error::DisplayError(e.to_string()))?;
+        self.ui.checked_write()?.keypad_buttons = buttons;
+
+        if let Some((protocol, size, area, scale)) = image {
+            self.draw_image(protocol, size, area, scale, intensities)?;
+        }
 
         Ok(())
     }
@@ -1,53 +1,243 @@
 use chip8_core::{
+    constants::TICKS_PER_TIMER,
+    control::ControlEvent,
     drivers::InputDriver,
     error::Chip8Error,
-    input::{InputEvent, InputKind},
+    input::{HostEvent, InputEvent, InputKind},
     keypad::Key,
+    movie::{Movie, MovieEncoding, MovieHeader, MovieWriter, Timebase},
+    rwlock::{CheckedRead, CheckedWrite},
+};
+use crossterm::event::{
+    poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+use ratatui::layout::Position;
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    time::{Duration, Instant},
 };
-use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
-use csv::Writer;
-use serde::{Deserialize, Serialize};
-use std::io::Write;
 
-const FREQUENCY: u64 = 120;
+use crate::{
+    keymap::{Action, HostKey, Keymap},
+    macros::{Autofire, Macro},
+    ui::SharedUiState,
+};
 
-fn keymap(c: char) -> Option<Key> {
-    match c {
-        '1' => Some(Key::Key1),
-        '2' => Some(Key::Key2),
-        '3' => Some(Key::Key3),
-        '4' => Some(Key::KeyC),
-        'Q' => Some(Key::Key4),
-        'W' => Some(Key::Key5),
-        'E' => Some(Key::Key6),
-        'R' => Some(Key::KeyD),
-        'A' => Some(Key::Key7),
-        'S' => Some(Key::Key8),
-        'D' => Some(Key::Key9),
-        'F' => Some(Key::KeyA),
-        'Z' => Some(Key::KeyA),
-        'X' => Some(Key::Key0),
-        'C' => Some(Key::KeyB),
-        'V' => Some(Key::KeyF),
-        _ => None,
-    }
-}
+const FREQUENCY: u64 = 120;
 
-#[derive(Serialize, Deserialize)]
-pub struct CsvRecord {
-    pub clk: u64,
-    pub key: char,
-    pub kind: u8,
+/// Movie being replayed, kept to rewrite it when the user takes over.
+pub struct Playback<W> {
+    /// Keyed by the clk the events are replayed at
+    pub movie: Movie,
+    pub encoding: MovieEncoding,
+    /// Timebase of the file, kept when it is rewritten
+    pub timebase: Timebase,
+    /// Opens the movie file for writing, truncating it
+    pub create: Box<dyn FnMut() -> io::Result<W> + Send>,
 }
 
 #[derive(Default)]
 pub struct TerminalKeyboardInput<W: Write> {
-    writer: Option<Writer<W>>,
+    writer: Option<MovieWriter<W>>,
+    playback: Option<Playback<W>>,
+    keymap: Keymap,
+    ui: SharedUiState,
+    // Set when the terminal can't report releases, which are then synthesized
+    key_hold: Option<Duration>,
+    // Keys considered held down, and when they will be released
+    held: Vec<(HostKey, Instant)>,
+    // On-screen keypad button under the mouse while the left button is down
+    clicked: Option<Key>,
+    autofire: Vec<Autofire>,
+    ticks_per_timer: u64,
+    recorded: Macro,
+    // Cycle of the last call to `scheduled`
+    clk: u64,
+    // Read before the event reader was, and handled first
+    typed_ahead: VecDeque<KeyEvent>,
 }
 
 impl<W: Write> TerminalKeyboardInput<W> {
-    pub fn new(writer: Option<Writer<W>>) -> Self {
-        Self { writer }
+    pub fn new(writer: Option<MovieWriter<W>>) -> Self {
+        Self {
+            writer,
+            playback: None,
+            keymap: Keymap::default(),
+            ui: SharedUiState::default(),
+            key_hold: None,
+            held: Vec::new(),
+            clicked: None,
+            autofire: Vec::new(),
+            ticks_per_timer: TICKS_PER_TIMER,
+            recorded: Macro::default(),
+            clk: 0,
+            typed_ahead: VecDeque::new(),
+        }
+    }
+
+    /// Lets the user take over the replay of `playback`.
+    pub fn with_playback(mut self, playback: Playback<W>) -> Self {
+        self.playback = Some(playback);
+        self
+    }
+
+    /// Cycles per frame, which autofire periods are counted in.
+    pub fn with_ticks_per_timer(mut self, ticks_per_timer: u64) -> Self {
+        self.ticks_per_timer = ticks_per_timer;
+        self
+    }
+
+    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    pub fn with_ui_state(mut self, ui: SharedUiState) -> Self {
+        self.ui = ui;
+        self
+    }
+
+    /// Releases keys `key_hold` after their last press, for terminals without
+    /// release events. Auto-repeat keeps a key held.
+    pub fn with_synthesized_releases(mut self, key_hold: Duration) -> Self {
+        self.key_hold = Some(key_hold);
+        self
+    }
+
+    /// Keys typed before the driver started reading.
+    pub fn with_typed_ahead(mut self, keys: Vec<KeyEvent>) -> Self {
+        self.typed_ahead = keys.into();
+        self
+    }
+
+    fn handle_event(&mut self, event: Event) -> Result<Option<HostEvent>, Chip8Error> {
+        match event {
+            Event::Key(KeyEvent {
+                code,
+                kind,
+                modifiers,
+                ..
+            }) => match (modifiers, code) {
+                (KeyModifiers::CONTROL, KeyCode::Char('c')) => Err(Chip8Error::Interrupt),
+                (_, KeyCode::Esc) => Err(Chip8Error::Interrupt),
+                _ => self.handle_key(code, kind),
+            },
+            Event::Mouse(event) => self.handle_mouse(event),
+            _ => Ok(None),
+        }
+    }
+
+    // The key is released when the button is, wherever the mouse went
+    fn handle_mouse(&mut self, event: MouseEvent) -> Result<Option<HostEvent>, Chip8Error> {
+        if self.ui.checked_read()?.read_only {
+            return Ok(None);
+        }
+        let MouseEvent {
+            kind, column, row, ..
+        } = event;
+        let (key, kind) = match kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let position = Position::new(column, row);
+                let ui = self.ui.checked_read()?;
+                let key = ui
+                    .keypad_buttons
+                    .iter()
+                    .find(|(button, _)| button.contains(position))
+                    .map(|(_, key)| *key);
+                self.clicked = key;
+                (key, InputKind::Press)
+            }
+            MouseEventKind::Up(MouseButton::Left) => (self.clicked.take(), InputKind::Release),
+            _ => (None, InputKind::Release),
+        };
+
+        Ok(key.map(|key| HostEvent::Key(InputEvent { key, kind })))
+    }
+
+    fn handle_key(
+        &mut self,
+        code: KeyCode,
+        kind: KeyEventKind,
+    ) -> Result<Option<HostEvent>, Chip8Error> {
+        if let Some(action) = self.keymap.action(code) {
+            if action == Action::TakeOver {
+                let take_over = kind == KeyEventKind::Press && self.playback.is_some();
+                return Ok(take_over.then_some(HostEvent::TakeOver));
+            }
+            // Recorded like key input, so not while read-only either
+            if action == Action::Reset {
+                let reset = kind == KeyEventKind::Press && !self.ui.checked_read()?.read_only;
+                return Ok(reset.then_some(HostEvent::Reset));
+            }
+            return Ok(self.control(action, kind)?.map(HostEvent::Control));
+        }
+        if self.ui.checked_read()?.read_only {
+            return Ok(None);
+        }
+
+        if let Some(binding) = self.keymap.autofire(code) {
+            let host_key = HostKey::new(code);
+            let active = self.autofire.iter().position(|a| a.host_key == host_key);
+            match (kind, active) {
+                (KeyEventKind::Press, None) => {
+                    self.autofire
+                        .push(Autofire::new(host_key, binding, self.ticks_per_timer));
+                }
+                (KeyEventKind::Release, Some(i)) => {
+                    let autofire = self.autofire.remove(i);
+                    return Ok(autofire.stop().map(HostEvent::Key));
+                }
+                _ => {}
+            }
+            return Ok(None);
+        }
+
+        let kind = match kind {
+            KeyEventKind::Press => Some(InputKind::Press),
+            KeyEventKind::Release => Some(InputKind::Release),
+            _ => None,
+        };
+        Ok(self
+            .keymap
+            .key(code)
+            .zip(kind)
+            .map(|(key, kind)| HostEvent::Key(InputEvent { key, kind })))
+    }
+
+    fn control(
+        &mut self,
+        action: Action,
+        kind: KeyEventKind,
+    ) -> Result<Option<ControlEvent>, Chip8Error> {
+        let event = match (action, kind) {
+            (Action::Turbo, KeyEventKind::Press) => Some(ControlEvent::Turbo(true)),
+            (Action::Turbo, KeyEventKind::Release) => Some(ControlEvent::Turbo(false)),
+            (_, KeyEventKind::Press) => match action {
+                Action::Help => {
+                    let mut ui = self.ui.checked_write()?;
+                    ui.show_help = !ui.show_help;
+                    None
+                }
+                Action::Pause => Some(ControlEvent::TogglePause),
+                Action::StepFrame => Some(ControlEvent::StepFrame),
+                Action::SpeedUp => Some(ControlEvent::SpeedUp),
+                Action::SlowDown => Some(ControlEvent::SlowDown),
+                Action::Turbo => None,
+                Action::RecordMacro => {
+                    self.recorded.toggle_recording(self.clk);
+                    None
+                }
+                Action::PlayMacro => {
+                    self.recorded.play(self.clk);
+                    None
+                }
+                Action::Reset | Action::TakeOver => None,
+            },
+            _ => None,
+        };
+        Ok(event)
     }
 }
 
@@ -57,50 +247,248 @@ impl<W: Write + Send> InputDriver for TerminalKeyboardInput<W> {
     }
 
     fn log_input(&mut self, clk: u64, input: InputEvent) -> Result<(), Chip8Error> {
+        self.recorded.record(clk, input);
+        // Kept for taking over later, which rewrites the file from it
+        if let Some(playback) = &mut self.playback {
+            playback.movie.events.push((clk, input));
+        }
+
+        if let Some(writer) = &mut self.writer {
+            writer
+                .write_event(clk, input)
+                .map_err(|e| Chip8Error::InputError(e.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn log_reset(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        if let Some(playback) = &mut self.playback {
+            playback.movie.resets.push(clk);
+        }
         if let Some(writer) = &mut self.writer {
-            let record = CsvRecord {
-                clk,
-                key: char::from(input.key),
-                kind: input.kind as u8,
-            };
             writer
-                .serialize(record)
+                .write_reset(clk)
                 .map_err(|e| Chip8Error::InputError(e.to_string()))
         } else {
             Ok(())
         }
     }
 
-    fn poll(&mut self) -> Result<Option<InputEvent>, Chip8Error> {
-        let event = read().map_err(|e| Chip8Error::InputError(e.to_string()))?;
-        if let Event::Key(KeyEvent {
-            code,
-            kind,
-            modifiers,
-            ..
-        }) = event
-        {
-            match (modifiers, code) {
-                (KeyModifiers::CONTROL, KeyCode::Char('c')) => return Err(Chip8Error::Interrupt),
-                (_, KeyCode::Esc) => return Err(Chip8Error::Interrupt),
-                (_, KeyCode::Char(c)) => {
-                    let kind = match kind {
-                        KeyEventKind::Press => Some(InputKind::Press),
-                        KeyEventKind::Release => Some(InputKind::Release),
-                        _ => None,
-                    };
-
-                    if let Some(kind) = kind {
-                        if let Some(key) = keymap(c.to_ascii_uppercase()) {
-                            let event = InputEvent { key, kind };
-                            return Ok(Some(event));
-                        }
-                    }
+    fn log_checkpoint(&mut self, clk: u64, checksum: u64) -> Result<(), Chip8Error> {
+        if let Some(playback) = &mut self.playback {
+            let checkpoints = &mut playback.movie.checkpoints;
+            if checkpoints.last().is_none_or(|(last, _)| clk > *last) {
+                checkpoints.push((clk, checksum));
+            }
+        }
+        if let Some(writer) = &mut self.writer {
+            writer
+                .write_checkpoint(clk, checksum)
+                .map_err(|e| Chip8Error::InputError(e.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn poll(&mut self) -> Result<Option<HostEvent>, Chip8Error> {
+        let now = Instant::now();
+        if let Some(i) = self.held.iter().position(|(_, until)| *until <= now) {
+            let (host_key, _) = self.held.remove(i);
+            return self.handle_key(host_key.code(), KeyEventKind::Release);
+        }
+
+        // Wait for input no longer than the next release, or the next poll so
+        // that scheduled input goes out and a cancelled run notices
+        let period = Duration::from_secs(1) / FREQUENCY as u32;
+        let timeout = self
+            .held
+            .iter()
+            .map(|(_, until)| *until - now)
+            .fold(period, Duration::min);
+        let event = match self.typed_ahead.pop_front() {
+            Some(key) => Event::Key(key),
+            None => {
+                let ready = poll(timeout).map_err(|e| Chip8Error::InputError(e.to_string()))?;
+                if !ready {
+                    return Ok(None);
+                }
+                read().map_err(|e| Chip8Error::InputError(e.to_string()))?
+            }
+        };
+        if let (Some(key_hold), Event::Key(KeyEvent { code, kind, .. })) = (self.key_hold, &event) {
+            let host_key = HostKey::new(*code);
+            let until = Instant::now() + key_hold;
+            let held = self.held.iter().position(|(k, _)| *k == host_key);
+            match (kind, held) {
+                // Auto-repeat arrives as more presses, which only extend the hold
+                (KeyEventKind::Press, Some(i)) => {
+                    self.held[i].1 = until;
+                    return Ok(None);
                 }
-                _ => return Ok(None),
+                (KeyEventKind::Press, None) if self.keymap.is_bound(*code) => {
+                    self.held.push((host_key, until));
+                }
+                (KeyEventKind::Release, Some(i)) => {
+                    self.held.remove(i);
+                }
+                _ => {}
             }
         }
 
+        self.handle_event(event)
+    }
+
+    fn take_over(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        let Some(mut playback) = self.playback.take() else {
+            return Ok(());
+        };
+        playback
+            .movie
+            .events
+            .retain(|(event_clk, _)| *event_clk <= clk);
+        playback.movie.resets.retain(|reset_clk| *reset_clk <= clk);
+        playback
+            .movie
+            .checkpoints
+            .retain(|(checkpoint_clk, _)| *checkpoint_clk <= clk);
+        let writer = rewrite(&mut playback).map_err(|e| Chip8Error::InputError(e.to_string()))?;
+        self.writer = Some(writer);
+
+        let mut ui = self.ui.checked_write()?;
+        ui.playback_end = None;
+        ui.read_only = false;
+        Ok(())
+    }
+
+    fn is_scheduling(&self) -> bool {
+        !self.autofire.is_empty() || self.recorded.is_active()
+    }
+
+    fn scheduled(&mut self, clk: u64) -> Result<Vec<InputEvent>, Chip8Error> {
+        self.clk = clk;
+        if self.ui.checked_read()?.read_only {
+            return Ok(vec![]);
+        }
+
+        let mut events = self
+            .autofire
+            .iter_mut()
+            .filter_map(|a| a.poll(clk))
+            .collect::<Vec<_>>();
+        events.extend(self.recorded.poll(clk));
+        Ok(events)
+    }
+}
+
+// Starts the movie over with what was kept of the replay
+fn rewrite<W: Write>(playback: &mut Playback<W>) -> io::Result<MovieWriter<W>> {
+    let movie = &playback.movie;
+    let header = MovieHeader {
+        timebase: playback.timebase,
+        ..movie.header.clone()
+    };
+    let file = (playback.create)()?;
+    let mut writer = MovieWriter::new(file, playback.encoding, &header, movie.start.as_ref())?;
+    for &(clk, event) in &movie.events {
+        writer.write_event(clk, event)?;
+    }
+    for &clk in &movie.resets {
+        writer.write_reset(clk)?;
+    }
+    for &(clk, checksum) in &movie.checkpoints {
+        writer.write_checkpoint(clk, checksum)?;
+    }
+    Ok(writer)
+}
+
+/// Input of batch runs, which only replay what was queued up front.
+pub struct NoInput;
+
+impl InputDriver for NoInput {
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    fn poll(&mut self) -> Result<Option<HostEvent>, Chip8Error> {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::movie::MovieHeader;
+    use std::sync::{Arc, Mutex};
+
+    // File contents shared with the test, emptied when created again
+    #[derive(Clone, Default)]
+    struct SharedFile(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn event(key: Key, kind: InputKind) -> InputEvent {
+        InputEvent { key, kind }
+    }
+
+    #[test]
+    fn take_over_keeps_input_recorded_after_playback() {
+        let mut movie = Movie::new(MovieHeader::new("00".into(), 0, 500));
+        movie.events = vec![(10, event(Key::Key5, InputKind::Press))];
+        let file = SharedFile::default();
+        file.clone()
+            .write_all(&movie.encode(MovieEncoding::Text))
+            .unwrap();
+
+        let writer = MovieWriter::append(file.clone(), MovieEncoding::Text, &movie);
+        let create = {
+            let file = file.clone();
+            move || {
+                file.0.lock().unwrap().clear();
+                Ok(file.clone())
+            }
+        };
+        let mut input = TerminalKeyboardInput::new(Some(writer)).with_playback(Playback {
+            movie,
+            encoding: MovieEncoding::Text,
+            timebase: Timebase::Cycles,
+            create: Box::new(create),
+        });
+
+        // Played live once the replay is over
+        input
+            .log_input(20, event(Key::Key5, InputKind::Release))
+            .unwrap();
+        input
+            .log_input(25, event(Key::KeyA, InputKind::Press))
+            .unwrap();
+        input.log_reset(22).unwrap();
+        input.take_over(30).unwrap();
+
+        let bytes = file.0.lock().unwrap().clone();
+        let movie = Movie::decode(&bytes).unwrap();
+        assert_eq!(movie.resets, vec![22]);
+        let events = movie
+            .events
+            .iter()
+            .map(|(clk, event)| (*clk, event.key.to_string(), event.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (10, Key::Key5.to_string(), InputKind::Press),
+                (20, Key::Key5.to_string(), InputKind::Release),
+                (25, Key::KeyA.to_string(), InputKind::Press),
+            ]
+        );
+    }
+}
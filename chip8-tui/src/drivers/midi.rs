@@ -0,0 +1,230 @@
+use chip8_core::{drivers::AudioDriver, error::Chip8Error};
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+const FREQUENCY: u64 = 60;
+
+const TIMER_FREQUENCY: u64 = 60;
+const MICROS_PER_SECOND: u64 = 1_000_000;
+// Ticks per quarter note must stay below 0x8000, which would mean SMPTE time,
+// and the tempo fit in 24 bits
+const MAX_DIVISION: u64 = 0x7FFF;
+// Longest delta time a variable-length quantity may hold, in 4 bytes
+const MAX_DELTA: u64 = 0x0FFF_FFFF;
+
+// CHIP-8 has a single fixed-pitch buzzer, A4
+const NOTE: u8 = 69;
+const VELOCITY: u8 = 100;
+
+// Header chunk, then the track chunk's tag and length
+const HEADER_LEN: u64 = 14;
+const TRACK_LEN_OFFSET: u64 = HEADER_LEN + 4;
+const END_OF_TRACK: [u8; 4] = [0x00, 0xFF, 0x2F, 0x00];
+
+/// Writes sound timer intervals as notes of a Type 0 Standard MIDI File.
+///
+/// Timestamps come from the emulated cycle count rather than the wall clock,
+/// so replaying the same inputs always produces the same file. One MIDI tick
+/// is one CPU cycle.
+pub struct MidiAudio {
+    path: PathBuf,
+    // MIDI ticks per quarter note
+    division: u16,
+    // Microseconds per quarter note
+    tempo: u32,
+    file: Option<File>,
+    // Events not written yet, and the clk of the last one
+    pending: Vec<u8>,
+    prev_clk: u64,
+    on: bool,
+    // Bytes of track data before the end of track event
+    track_len: u64,
+}
+
+impl MidiAudio {
+    /// Fails if no tempo can time a cycle exactly at `ticks_per_timer`.
+    pub fn new(path: PathBuf, ticks_per_timer: u64) -> Result<Self, Chip8Error> {
+        let (division, tempo) = timing(ticks_per_timer).ok_or_else(|| {
+            Chip8Error::AudioError(format!(
+                "No MIDI tempo matches {ticks_per_timer} ticks per timer"
+            ))
+        })?;
+        Ok(Self {
+            path,
+            division,
+            tempo,
+            file: None,
+            pending: vec![],
+            prev_clk: 0,
+            on: false,
+            track_len: 0,
+        })
+    }
+
+    fn push(&mut self, clk: u64, on: bool) {
+        let mut delta = clk.saturating_sub(self.prev_clk);
+        // Longer gaps are bridged with empty text events
+        while delta > MAX_DELTA {
+            write_vlq(&mut self.pending, MAX_DELTA);
+            self.pending.extend([0xFF, 0x01, 0x00]);
+            delta -= MAX_DELTA;
+        }
+        write_vlq(&mut self.pending, delta);
+        if on {
+            self.pending.extend([0x90, NOTE, VELOCITY]);
+        } else {
+            self.pending.extend([0x80, NOTE, 0]);
+        }
+        self.prev_clk = clk;
+        self.on = on;
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(b"MThd");
+        bytes.extend(6u32.to_be_bytes());
+        // Format 0, single track
+        bytes.extend(0u16.to_be_bytes());
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(self.division.to_be_bytes());
+        bytes.extend(b"MTrk");
+        // Patched by every save
+        bytes.extend(0u32.to_be_bytes());
+        // Tempo
+        bytes.extend([0x00, 0xFF, 0x51, 0x03]);
+        bytes.extend(&self.tempo.to_be_bytes()[1..]);
+        bytes
+    }
+
+    // Appends the pending events over the previous end of track and patches
+    // the track length, so that the file stays valid even if the process is
+    // killed.
+    fn save(&mut self) -> Result<(), Chip8Error> {
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => {
+                let mut file = File::create(&self.path).map_err(audio_error)?;
+                let header = self.header();
+                file.write_all(&header).map_err(audio_error)?;
+                self.track_len = header.len() as u64 - HEADER_LEN - 8;
+                file
+            }
+        };
+        let pending = std::mem::take(&mut self.pending);
+        file.seek(SeekFrom::Start(HEADER_LEN + 8 + self.track_len))
+            .map_err(audio_error)?;
+        file.write_all(&pending).map_err(audio_error)?;
+        file.write_all(&END_OF_TRACK).map_err(audio_error)?;
+        self.track_len += pending.len() as u64;
+
+        let len = u32::try_from(self.track_len + END_OF_TRACK.len() as u64)
+            .map_err(|_| Chip8Error::AudioError("MIDI track too long".to_string()))?;
+        file.seek(SeekFrom::Start(TRACK_LEN_OFFSET))
+            .map_err(audio_error)?;
+        file.write_all(&len.to_be_bytes()).map_err(audio_error)?;
+        file.flush().map_err(audio_error)?;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+// Ticks per quarter note and microseconds per quarter note for one tick per
+// cycle: a quarter note is one second when that fits, and otherwise as short
+// as it gets
+fn timing(ticks_per_timer: u64) -> Option<(u16, u32)> {
+    let cycles_per_second = TIMER_FREQUENCY.checked_mul(ticks_per_timer)?;
+    if cycles_per_second == 0 {
+        return None;
+    }
+    if cycles_per_second <= MAX_DIVISION {
+        return Some((cycles_per_second as u16, MICROS_PER_SECOND as u32));
+    }
+    let common = gcd(cycles_per_second, MICROS_PER_SECOND);
+    let division = cycles_per_second / common;
+    (division <= MAX_DIVISION).then_some((division as u16, (MICROS_PER_SECOND / common) as u32))
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+fn audio_error(e: std::io::Error) -> Chip8Error {
+    Chip8Error::AudioError(e.to_string())
+}
+
+fn write_vlq(bytes: &mut Vec<u8>, mut value: u64) {
+    let mut buf = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        buf.push(0x80 | (value & 0x7F) as u8);
+        value >>= 7;
+    }
+    bytes.extend(buf.iter().rev());
+}
+
+impl AudioDriver for MidiAudio {
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    fn sound_on(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        self.push(clk, true);
+        Ok(())
+    }
+
+    fn sound_off(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        self.push(clk, false);
+        self.save()
+    }
+
+    fn finish(&mut self, clk: u64) -> Result<(), Chip8Error> {
+        if self.on {
+            self.push(clk, false);
+        }
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_one_tick_per_cycle() {
+        for ticks_per_timer in [1, 8, 546, 547, 1000, 3003] {
+            let (division, tempo) = timing(ticks_per_timer).unwrap();
+            // Ticks per second, from both sides
+            assert_eq!(
+                division as u64 * MICROS_PER_SECOND,
+                TIMER_FREQUENCY * ticks_per_timer * tempo as u64
+            );
+            assert!(division as u64 <= MAX_DIVISION);
+        }
+        assert_eq!(timing(8), Some((480, 1_000_000)));
+        assert_eq!(timing(0), None);
+        assert_eq!(timing(20011), None);
+    }
+
+    #[test]
+    fn splits_long_gaps() {
+        let mut midi = MidiAudio::new(PathBuf::new(), 8).unwrap();
+        midi.push(MAX_DELTA * 2 + 5, true);
+        assert_eq!(
+            midi.pending,
+            [
+                [0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x01, 0x00],
+                [0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x01, 0x00],
+            ]
+            .concat()
+            .into_iter()
+            .chain([0x05, 0x90, NOTE, VELOCITY])
+            .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,3 +1,4 @@
 pub(crate) mod audio;
 pub(crate) mod display;
 pub(crate) mod input;
+pub(crate) mod midi;
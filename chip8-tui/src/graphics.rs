@@ -0,0 +1,176 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::collections::HashMap;
+
+// Kitty limits the payload of a single escape sequence
+const KITTY_CHUNK_SIZE: usize = 4096;
+const KITTY_IMAGE_ID: u32 = 1;
+const KITTY_PLACEMENT_ID: u32 = 1;
+
+/// Query sent by `setup_terminal`: a kitty graphics query followed by a
+/// primary device attributes request, which every terminal answers.
+pub const GRAPHICS_QUERY: &str = "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    Kitty,
+    Sixel,
+}
+
+impl GraphicsProtocol {
+    /// Parses the terminal's answer to [`GRAPHICS_QUERY`], preferring kitty.
+    pub fn detect(response: &str) -> Option<Self> {
+        if response.contains("\x1b_Gi=31;OK") {
+            return Some(Self::Kitty);
+        }
+
+        // Device attributes: ESC [ ? Ps ; ... ; Ps c, where 4 means sixel
+        let start = response.find("\x1b[?")? + 3;
+        let end = start + response[start..].find('c')?;
+        response[start..end]
+            .split(';')
+            .any(|param| param == "4")
+            .then_some(Self::Sixel)
+    }
+
+    /// Returns true once `response` contains the device attributes answer.
+    pub fn is_complete(response: &str) -> bool {
+        response
+            .find("\x1b[?")
+            .is_some_and(|start| response[start..].contains('c'))
+    }
+
+    /// Removes the last image from the screen, for protocols where text drawn
+    /// over it would not.
+    pub fn clear(&self) -> String {
+        match self {
+            Self::Kitty => format!("\x1b_Ga=d,d=i,i={KITTY_IMAGE_ID},q=2\x1b\\"),
+            Self::Sixel => String::new(),
+        }
+    }
+
+    /// Encodes `image` to cover `cells` (columns, rows) at an integer
+    /// `scale`. Kitty scales it to the cells itself, while sixel has no
+    /// such parameter and repeats each pixel `scale` times instead.
+    pub fn encode(&self, image: &Image, scale: usize, cells: (u16, u16)) -> String {
+        match self {
+            Self::Kitty => encode_kitty(image, cells),
+            Self::Sixel => encode_sixel(image, scale.max(1)),
+        }
+    }
+}
+
+/// RGB bitmap, row-major.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    /// Takes `colors` as is, one row per pixel row.
+    pub fn new<R: AsRef<[[u8; 3]]>>(colors: &[R]) -> Self {
+        let height = colors.len();
+        let width = colors.first().map_or(0, |row| row.as_ref().len());
+        let pixels = colors
+            .iter()
+            .flat_map(|row| row.as_ref().iter().copied())
+            .collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+fn encode_kitty(image: &Image, (columns, rows): (u16, u16)) -> String {
+    let bytes = image.pixels.iter().flatten().copied().collect::<Vec<_>>();
+    let payload = STANDARD.encode(bytes);
+    let chunks = payload
+        .as_bytes()
+        .chunks(KITTY_CHUNK_SIZE)
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        // Only the first chunk carries the control keys
+        let keys = if i == 0 {
+            format!(
+                "a=T,f=24,s={},v={},c={columns},r={rows},i={KITTY_IMAGE_ID},p={KITTY_PLACEMENT_ID},q=2,C=1,m={more}",
+                image.width, image.height,
+            )
+        } else {
+            format!("m={more}")
+        };
+        out.push_str(&format!(
+            "\x1b_G{keys};{}\x1b\\",
+            String::from_utf8_lossy(chunk)
+        ));
+    }
+    out
+}
+
+fn encode_sixel(image: &Image, scale: usize) -> String {
+    let mut palette = HashMap::new();
+    let indices = image
+        .pixels
+        .iter()
+        .map(|color| {
+            let next = palette.len();
+            *palette.entry(*color).or_insert(next)
+        })
+        .collect::<Vec<_>>();
+
+    let (width, height) = (image.width * scale, image.height * scale);
+    let mut out = format!("\x1bP0;1;0q\"1;1;{width};{height}");
+    let mut colors = palette.iter().collect::<Vec<_>>();
+    colors.sort_by_key(|(_, &index)| index);
+    for ([r, g, b], index) in colors {
+        let percent = |c: &u8| *c as u32 * 100 / 255;
+        out.push_str(&format!(
+            "#{index};2;{};{};{}",
+            percent(r),
+            percent(g),
+            percent(b)
+        ));
+    }
+
+    // Each sixel character encodes a column of 6 pixels, which are looked up
+    // in the image at its own size. Columns are repeated through run lengths.
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        for index in 0..palette.len() {
+            let column = |x: usize| {
+                rows.clone()
+                    .filter(|&y| indices[y / scale * image.width + x] == index)
+                    .fold(0, |acc, y| acc | 1 << (y - band))
+            };
+            if (0..image.width).all(|x| column(x) == 0) {
+                continue;
+            }
+
+            out.push_str(&format!("#{index}"));
+            let mut x = 0;
+            while x < image.width {
+                let bits = column(x);
+                let run = (x..image.width).take_while(|&x| column(x) == bits).count();
+                let c = char::from(63 + bits as u8);
+                let repeat = run * scale;
+                if repeat > 3 {
+                    out.push_str(&format!("!{repeat}{c}"));
+                } else {
+                    out.extend(std::iter::repeat_n(c, repeat));
+                }
+                x += run;
+            }
+            // Carriage return to draw the next color over the same band
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+
+    out
+}
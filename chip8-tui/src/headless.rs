@@ -0,0 +1,109 @@
+use chip8_core::{cpu::Cpu, drivers::AudioDriver, state::SaveState, Chip8, ExitReason};
+use clap::ValueEnum;
+use eyre::Result;
+use ratatui::backend::CrosstermBackend;
+use serde::Serialize;
+use std::io::Stdout;
+
+use crate::drivers::{display::TerminalDisplay, input::NoInput};
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// State of the machine at the end of a batch run.
+#[derive(Serialize)]
+struct Report {
+    /// Why the run ended
+    reason: String,
+    cycles: u64,
+    frames: u64,
+    v: Vec<u8>,
+    i: u16,
+    pc: u16,
+    sp: u8,
+    dt: u8,
+    st: u8,
+    /// Faults let through by `--on-fault`
+    suppressed_faults: u64,
+    /// Rows of `#` (on) and `.` (off)
+    screen: Vec<String>,
+}
+
+impl Report {
+    fn new(
+        reason: String,
+        state: &SaveState,
+        suppressed_faults: u64,
+        ticks_per_timer: u64,
+    ) -> Self {
+        Self {
+            reason,
+            cycles: state.clk,
+            frames: state.clk / ticks_per_timer,
+            v: state.registers.to_vec(),
+            i: state.index_register,
+            pc: state.program_counter,
+            sp: state.stack_pointer,
+            dt: state.delay_timer,
+            st: state.sound_timer,
+            suppressed_faults,
+            screen: state.screen_text().lines().map(str::to_string).collect(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let hex = |v: &[u8]| {
+            v.iter()
+                .map(|x| format!("{x:02X}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "{}\nCycles: {} ({} frames)\nV0-V7: {}\nV8-VF: {}\nI: 0x{:03X}  PC: 0x{:03X}  SP: {}  DT: {}  ST: {}\n{}",
+            self.reason,
+            self.cycles,
+            self.frames,
+            hex(&self.v[..8]),
+            hex(&self.v[8..]),
+            self.i,
+            self.pc,
+            self.sp,
+            self.dt,
+            self.st,
+            self.screen.join("\n")
+        )
+    }
+}
+
+/// Runs the machine with no terminal and no input but what it was created
+/// with, until a stop condition is met, then prints a report.
+pub async fn run<C: Cpu + Send + Sync + 'static>(
+    chip8: &mut Chip8<C>,
+    audio: Option<Box<dyn AudioDriver>>,
+    format: ReportFormat,
+    ticks_per_timer: u64,
+) -> Result<ExitReason> {
+    let reason = chip8
+        .run(
+            NoInput,
+            None::<TerminalDisplay<CrosstermBackend<Stdout>>>,
+            audio,
+        )
+        .await;
+
+    let report = Report::new(
+        reason.to_string(),
+        &chip8.save_state()?,
+        chip8.trace()?.suppressed,
+        ticks_per_timer,
+    );
+    match format {
+        ReportFormat::Text => println!("{}", report.text()),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(reason)
+}
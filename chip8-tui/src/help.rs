@@ -0,0 +1,56 @@
+use chip8_core::keypad::KEYPAD_LAYOUT;
+use ratatui::{
+    style::{Color, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph},
+};
+
+use crate::keymap::{HostKey, Keymap, ACTIONS};
+
+pub const HELP_WIDTH: u16 = 42;
+
+/// Overlay listing the active key bindings, and its height.
+pub fn help_overlay(keymap: &Keymap, border_color: Color) -> (Paragraph<'static>, u16) {
+    let mut lines = vec![Line::from("Keypad".bold())];
+    for row in KEYPAD_LAYOUT {
+        let cells = row
+            .iter()
+            .map(|&key| format!("{key}:{:<5}", join(&keymap.keys_for(key))))
+            .collect::<Vec<_>>();
+        lines.push(Line::from(cells.join(" ")));
+    }
+
+    lines.push(Line::default());
+    lines.push(Line::from("Emulator".bold()));
+    for action in ACTIONS {
+        lines.push(Line::from(format!(
+            "{:<13}{}",
+            action.to_string(),
+            join(&keymap.keys_for_action(action))
+        )));
+    }
+    lines.push(Line::from(format!("{:<13}Esc, Ctrl-C", "Quit")));
+
+    let autofire = keymap.autofire_bindings();
+    if !autofire.is_empty() {
+        lines.push(Line::default());
+        lines.push(Line::from("Autofire".bold()));
+        for (host_key, binding) in autofire {
+            lines.push(Line::from(format!(
+                "{host_key:<5}{} every {} frames",
+                binding.key, binding.frames
+            )));
+        }
+    }
+
+    let height = lines.len() as u16 + 2;
+    let overlay = Paragraph::new(lines).block(Block::bordered().title("Keys").fg(border_color));
+    (overlay, height)
+}
+
+fn join(keys: &[HostKey]) -> String {
+    keys.iter()
+        .map(|key| key.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
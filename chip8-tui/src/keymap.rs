@@ -0,0 +1,392 @@
+use chip8_core::keypad::{Key, KEYPAD_LAYOUT};
+use clap::ValueEnum;
+use crossterm::event::KeyCode;
+use eyre::{bail, eyre, Result};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+/// Host key layouts, arranged so that the CHIP-8 keypad keeps its shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeymapPreset {
+    #[default]
+    Qwerty,
+    Azerty,
+    Dvorak,
+    Numpad,
+}
+
+/// Emulator bindings, handled by the frontend instead of the CHIP-8 keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Help,
+    Pause,
+    StepFrame,
+    SpeedUp,
+    SlowDown,
+    Turbo,
+    Reset,
+    /// Start or stop recording the macro
+    RecordMacro,
+    PlayMacro,
+    /// Stop replaying the movie and record from the current clk
+    TakeOver,
+}
+
+pub const ACTIONS: [Action; 10] = [
+    Action::Help,
+    Action::Pause,
+    Action::StepFrame,
+    Action::SpeedUp,
+    Action::SlowDown,
+    Action::Turbo,
+    Action::Reset,
+    Action::RecordMacro,
+    Action::PlayMacro,
+    Action::TakeOver,
+];
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Help => "Help",
+            Self::Pause => "Pause",
+            Self::StepFrame => "Step frame",
+            Self::SpeedUp => "Speed up",
+            Self::SlowDown => "Slow down",
+            Self::Turbo => "Turbo (hold)",
+            Self::Reset => "Reset",
+            Self::RecordMacro => "Record macro",
+            Self::PlayMacro => "Play macro",
+            Self::TakeOver => "Take over",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A key on the host keyboard, written in config files as a single character
+/// or a name such as `space`, `enter` or `f5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HostKey(KeyCode);
+
+impl HostKey {
+    /// Characters are matched case-insensitively.
+    pub fn new(code: KeyCode) -> Self {
+        match code {
+            KeyCode::Char(c) => Self(KeyCode::Char(c.to_ascii_lowercase())),
+            code => Self(code),
+        }
+    }
+
+    pub fn code(&self) -> KeyCode {
+        self.0
+    }
+}
+
+impl FromStr for HostKey {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(Self::new(KeyCode::Char(c)));
+        }
+
+        let code = match s.to_ascii_lowercase().as_str() {
+            "space" => KeyCode::Char(' '),
+            "tab" => KeyCode::Tab,
+            "enter" => KeyCode::Enter,
+            "backspace" => KeyCode::Backspace,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "insert" => KeyCode::Insert,
+            "delete" => KeyCode::Delete,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => bail!("Unknown key {s}"),
+            },
+        };
+        Ok(Self::new(code))
+    }
+}
+
+impl Display for HostKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_uppercase()),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// Bindings read from a config file. Entries replace the preset's bindings
+/// for the CHIP-8 key or action they name.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyConfig {
+    pub preset: Option<KeymapPreset>,
+    /// CHIP-8 key (`0`-`F`) to host keys
+    pub keypad: HashMap<String, Vec<String>>,
+    pub controls: HashMap<Action, Vec<String>>,
+    /// Host key to the CHIP-8 key it rapidly presses while held
+    pub autofire: HashMap<String, AutofireConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutofireConfig {
+    pub key: String,
+    /// Frames the key stays pressed, and then released, in each cycle
+    #[serde(default = "default_autofire_frames")]
+    pub frames: u64,
+}
+
+impl KeyConfig {
+    /// Fails if a host key is bound more than once, as which binding wins
+    /// would depend on the order the tables are read in.
+    pub fn check(&self) -> Result<()> {
+        let mut host_keys = self
+            .keypad
+            .values()
+            .chain(self.controls.values())
+            .map(|names| parse_host_keys(names))
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        for name in self.autofire.keys() {
+            host_keys.push(name.parse()?);
+        }
+
+        let mut seen = HashSet::new();
+        match host_keys
+            .into_iter()
+            .find(|host_key| !seen.insert(*host_key))
+        {
+            Some(host_key) => bail!("Host key {host_key} is bound more than once"),
+            None => Ok(()),
+        }
+    }
+}
+
+fn default_autofire_frames() -> u64 {
+    2
+}
+
+/// CHIP-8 key toggled every `frames` frames while its host key is held.
+#[derive(Debug, Clone, Copy)]
+pub struct AutofireBinding {
+    pub key: Key,
+    pub frames: u64,
+}
+
+/// Lookup from host keys to CHIP-8 keys and emulator actions.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keypad: HashMap<HostKey, Key>,
+    controls: HashMap<HostKey, Action>,
+    autofire: HashMap<HostKey, AutofireBinding>,
+}
+
+impl Keymap {
+    pub fn preset(preset: KeymapPreset) -> Self {
+        // Host keys in KEYPAD_LAYOUT order
+        let layout: [[&[char]; 4]; 4] = match preset {
+            KeymapPreset::Qwerty => [
+                [&['1'], &['2'], &['3'], &['4']],
+                [&['q'], &['w'], &['e'], &['r']],
+                [&['a'], &['s'], &['d'], &['f']],
+                [&['z'], &['x'], &['c'], &['v']],
+            ],
+            // The digit row needs shift, so the unshifted symbols are bound too
+            KeymapPreset::Azerty => [
+                [&['1', '&'], &['2', 'é'], &['3', '"'], &['4', '\'']],
+                [&['a'], &['z'], &['e'], &['r']],
+                [&['q'], &['s'], &['d'], &['f']],
+                [&['w'], &['x'], &['c'], &['v']],
+            ],
+            KeymapPreset::Dvorak => [
+                [&['1'], &['2'], &['3'], &['4']],
+                [&['\''], &[','], &['.'], &['p']],
+                [&['a'], &['o'], &['e'], &['u']],
+                [&[';'], &['q'], &['j'], &['k']],
+            ],
+            KeymapPreset::Numpad => [
+                [&['7'], &['8'], &['9'], &['/']],
+                [&['4'], &['5'], &['6'], &['*']],
+                [&['1'], &['2'], &['3'], &['-']],
+                [&['0'], &['.'], &['\n'], &['+']],
+            ],
+        };
+
+        let mut keypad = HashMap::new();
+        for (keys, chars) in KEYPAD_LAYOUT.iter().zip(layout) {
+            for (&key, chars) in keys.iter().zip(chars) {
+                for &c in chars {
+                    let code = match c {
+                        '\n' => KeyCode::Enter,
+                        c => KeyCode::Char(c),
+                    };
+                    keypad.insert(HostKey::new(code), key);
+                }
+            }
+        }
+
+        let controls = [
+            (KeyCode::Char('?'), Action::Help),
+            (KeyCode::F(1), Action::Help),
+            (KeyCode::Char(' '), Action::Pause),
+            (KeyCode::Char('\\'), Action::StepFrame),
+            (KeyCode::Char(']'), Action::SpeedUp),
+            (KeyCode::Char('['), Action::SlowDown),
+            (KeyCode::Tab, Action::Turbo),
+            (KeyCode::F(5), Action::Reset),
+            (KeyCode::F(6), Action::RecordMacro),
+            (KeyCode::F(7), Action::PlayMacro),
+            (KeyCode::F(8), Action::TakeOver),
+        ]
+        .into_iter()
+        .map(|(code, action)| (HostKey::new(code), action))
+        .collect();
+
+        Self {
+            keypad,
+            controls,
+            autofire: HashMap::new(),
+        }
+    }
+
+    /// Applies the overrides in `config` on top of the current bindings. A host
+    /// key bound by `config` is removed from whatever it was bound to before.
+    pub fn apply(&mut self, config: &KeyConfig) -> Result<()> {
+        for (name, host_keys) in &config.keypad {
+            let key = parse_key(name)?;
+            let host_keys = parse_host_keys(host_keys)?;
+
+            self.keypad.retain(|_, k| *k as usize != key as usize);
+            for host_key in host_keys {
+                self.unbind(host_key);
+                self.keypad.insert(host_key, key);
+            }
+        }
+
+        for (&action, host_keys) in &config.controls {
+            let host_keys = parse_host_keys(host_keys)?;
+
+            self.controls.retain(|_, a| *a != action);
+            for host_key in host_keys {
+                self.unbind(host_key);
+                self.controls.insert(host_key, action);
+            }
+        }
+
+        for (name, autofire) in &config.autofire {
+            let host_key = name.parse()?;
+            let binding = AutofireBinding {
+                key: parse_key(&autofire.key)?,
+                frames: autofire.frames.max(1),
+            };
+
+            self.unbind(host_key);
+            self.autofire.insert(host_key, binding);
+        }
+
+        Ok(())
+    }
+
+    fn unbind(&mut self, host_key: HostKey) {
+        self.keypad.remove(&host_key);
+        self.controls.remove(&host_key);
+        self.autofire.remove(&host_key);
+    }
+
+    pub fn key(&self, code: KeyCode) -> Option<Key> {
+        self.keypad.get(&HostKey::new(code)).copied()
+    }
+
+    pub fn action(&self, code: KeyCode) -> Option<Action> {
+        self.controls.get(&HostKey::new(code)).copied()
+    }
+
+    pub fn autofire(&self, code: KeyCode) -> Option<AutofireBinding> {
+        self.autofire.get(&HostKey::new(code)).copied()
+    }
+
+    /// Returns true if `code` is bound to anything.
+    pub fn is_bound(&self, code: KeyCode) -> bool {
+        let host_key = HostKey::new(code);
+        self.keypad.contains_key(&host_key)
+            || self.controls.contains_key(&host_key)
+            || self.autofire.contains_key(&host_key)
+    }
+
+    /// Autofire bindings, sorted by host key for display.
+    pub fn autofire_bindings(&self) -> Vec<(HostKey, AutofireBinding)> {
+        let mut bindings = self
+            .autofire
+            .iter()
+            .map(|(host_key, binding)| (*host_key, *binding))
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|(host_key, _)| host_key.to_string());
+        bindings
+    }
+
+    /// Host keys bound to `key`, sorted for display.
+    pub fn keys_for(&self, key: Key) -> Vec<HostKey> {
+        sorted(
+            self.keypad
+                .iter()
+                .filter(|(_, &k)| k as usize == key as usize)
+                .map(|(host_key, _)| *host_key),
+        )
+    }
+
+    /// Host keys bound to `action`, sorted for display.
+    pub fn keys_for_action(&self, action: Action) -> Vec<HostKey> {
+        sorted(
+            self.controls
+                .iter()
+                .filter(|(_, &a)| a == action)
+                .map(|(host_key, _)| *host_key),
+        )
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::preset(KeymapPreset::default())
+    }
+}
+
+fn parse_key(name: &str) -> Result<Key> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => {
+            Key::try_from(c.to_ascii_uppercase()).map_err(|_| eyre!("Unknown CHIP-8 key {name}"))
+        }
+        _ => bail!("Unknown CHIP-8 key {name}"),
+    }
+}
+
+fn parse_host_keys(names: &[String]) -> Result<Vec<HostKey>> {
+    names.iter().map(|name| name.parse()).collect()
+}
+
+fn sorted(keys: impl Iterator<Item = HostKey>) -> Vec<HostKey> {
+    let mut keys = keys.collect::<Vec<_>>();
+    keys.sort_by_key(|key| key.to_string());
+    keys
+}
@@ -0,0 +1,57 @@
+use chip8_core::{
+    keypad::{Key, KEYPAD_LAYOUT},
+    state::StateSnapshot,
+};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+};
+
+const BUTTON_WIDTH: u16 = 5;
+const BUTTON_HEIGHT: u16 = 3;
+
+pub const KEYPAD_WIDTH: u16 = 4 * BUTTON_WIDTH + 2;
+pub const KEYPAD_HEIGHT: u16 = 4 * BUTTON_HEIGHT + 2;
+
+/// On-screen hex keypad, with the keys held by the CPU reversed.
+pub fn keypad(state: &StateSnapshot, border_color: Color) -> Paragraph<'static> {
+    let mut lines = vec![];
+    for row in KEYPAD_LAYOUT {
+        let (mut top, mut middle, mut bottom) = (vec![], vec![], vec![]);
+        for key in row {
+            let style = if state.keypad[key as usize] {
+                Style::new().reversed()
+            } else {
+                Style::new()
+            };
+            top.push(Span::styled("┌───┐", style));
+            middle.push(Span::styled(format!("│ {key} │"), style));
+            bottom.push(Span::styled("└───┘", style));
+        }
+        lines.extend([Line::from(top), Line::from(middle), Line::from(bottom)]);
+    }
+
+    Paragraph::new(lines).block(Block::bordered().title("Keypad").fg(border_color))
+}
+
+/// Screen area of every button when [`keypad`] is drawn in `area`.
+pub fn keypad_buttons(area: Rect) -> Vec<(Rect, Key)> {
+    let inner = Block::bordered().inner(area);
+    KEYPAD_LAYOUT
+        .iter()
+        .enumerate()
+        .flat_map(|(row, keys)| {
+            keys.iter().enumerate().map(move |(col, &key)| {
+                let button = Rect::new(
+                    inner.x + col as u16 * BUTTON_WIDTH,
+                    inner.y + row as u16 * BUTTON_HEIGHT,
+                    BUTTON_WIDTH,
+                    BUTTON_HEIGHT,
+                );
+                (button.intersection(inner), key)
+            })
+        })
+        .collect()
+}
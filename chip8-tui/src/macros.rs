@@ -0,0 +1,140 @@
+use chip8_core::{
+    input::{InputEvent, InputKind},
+    keypad::Key,
+};
+
+use crate::keymap::{AutofireBinding, HostKey};
+
+/// A CHIP-8 key being toggled while its autofire host key is held.
+#[derive(Debug, Clone, Copy)]
+pub struct Autofire {
+    pub host_key: HostKey,
+    key: Key,
+    // Cycles between two toggles
+    period: u64,
+    pressed: bool,
+    // Cycle of the next toggle, unknown until the first poll
+    next: Option<u64>,
+}
+
+impl Autofire {
+    pub fn new(host_key: HostKey, binding: AutofireBinding, ticks_per_timer: u64) -> Self {
+        Self {
+            host_key,
+            key: binding.key,
+            period: binding.frames.saturating_mul(ticks_per_timer),
+            pressed: false,
+            next: None,
+        }
+    }
+
+    /// Toggles the key if it is due at `clk`.
+    pub fn poll(&mut self, clk: u64) -> Option<InputEvent> {
+        if self.next.is_some_and(|next| clk < next) {
+            return None;
+        }
+
+        self.pressed = !self.pressed;
+        self.next = Some(clk + self.period);
+        Some(InputEvent {
+            key: self.key,
+            kind: if self.pressed {
+                InputKind::Press
+            } else {
+                InputKind::Release
+            },
+        })
+    }
+
+    /// Release that leaves the key up, if it is down.
+    pub fn stop(&self) -> Option<InputEvent> {
+        self.pressed.then_some(InputEvent {
+            key: self.key,
+            kind: InputKind::Release,
+        })
+    }
+}
+
+/// Input recorded with offsets relative to the start of the recording, and
+/// replayed relative to the cycle where playback starts.
+#[derive(Debug, Clone, Default)]
+pub struct Macro {
+    events: Vec<(u64, InputEvent)>,
+    // Cycle the recording started at
+    recording: Option<u64>,
+    // Cycle the playback started at, and the next event to play
+    playing: Option<(u64, usize)>,
+    // Keys pressed during the recording and not released yet
+    held: Vec<Key>,
+}
+
+impl Macro {
+    pub fn is_active(&self) -> bool {
+        self.recording.is_some() || self.playing.is_some()
+    }
+
+    /// Starts a new recording, or ends the current one. Keys still down at the
+    /// end are released so that playback leaves the keypad as it found it.
+    pub fn toggle_recording(&mut self, clk: u64) {
+        if self.playing.is_some() {
+            return;
+        }
+
+        match self.recording.take() {
+            Some(start) => {
+                for key in self.held.drain(..) {
+                    let release = InputEvent {
+                        key,
+                        kind: InputKind::Release,
+                    };
+                    self.events.push((clk - start, release));
+                }
+            }
+            None => {
+                self.events.clear();
+                self.held.clear();
+                self.recording = Some(clk);
+            }
+        }
+    }
+
+    /// Adds `event` to the recording. Replayed events are not recorded again.
+    pub fn record(&mut self, clk: u64, event: InputEvent) {
+        let (Some(start), None) = (self.recording, self.playing) else {
+            return;
+        };
+
+        let key = event.key as usize;
+        match event.kind {
+            InputKind::Press => {
+                if !self.held.iter().any(|k| *k as usize == key) {
+                    self.held.push(event.key);
+                }
+            }
+            InputKind::Release => self.held.retain(|k| *k as usize != key),
+        }
+        self.events.push((clk - start, event));
+    }
+
+    pub fn play(&mut self, clk: u64) {
+        if self.recording.is_none() && !self.events.is_empty() {
+            self.playing = Some((clk, 0));
+        }
+    }
+
+    /// Recorded events due at `clk`.
+    pub fn poll(&mut self, clk: u64) -> Vec<InputEvent> {
+        let Some((start, next)) = self.playing else {
+            return vec![];
+        };
+
+        let due = self.events[next..]
+            .iter()
+            .take_while(|(offset, _)| start + offset <= clk)
+            .map(|(_, event)| *event)
+            .collect::<Vec<_>>();
+        let next = next + due.len();
+        self.playing = (next < self.events.len()).then_some((start, next));
+        due
+    }
+}
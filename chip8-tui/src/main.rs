@@ -1,92 +1,291 @@
 mod args;
+mod config;
+mod crash;
 mod drivers;
+mod graphics;
+mod headless;
+mod help;
+mod keymap;
+mod keypad;
+mod macros;
+mod movie;
+mod panel;
+mod render;
+mod scenario;
 mod terminal;
+mod ui;
+mod verify;
 
-use args::CmdArgs;
+use args::{CmdArgs, Command};
 use chip8_core::{
     cpu::SimpleCpu,
-    input::{InputEvent, InputKind},
-    keypad::Key,
-    Chip8,
+    drivers::AudioDriver,
+    fault::FaultAction,
+    movie::{MovieEncoding, MovieHeader, Timebase},
+    rwlock::CheckedWrite,
+    Chip8, ExitReason,
 };
 use clap::Parser;
-use csv::{Reader, Writer, WriterBuilder};
-use drivers::input::CsvRecord;
-use eyre::Result;
-use rand::{random, rngs::StdRng, SeedableRng};
-use std::fs::{self, OpenOptions};
+use eyre::{bail, Result};
+use rand::{random, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use std::{
+    fs::{self, File},
+    process::ExitCode,
+};
 use terminal::{restore_terminal, setup_terminal};
 
-use crate::drivers::{
-    audio::TerminalAudio, display::TerminalDisplay, input::TerminalKeyboardInput,
+use crate::{
+    config::{rom_hash, Config},
+    drivers::{
+        audio::TerminalAudio,
+        display::TerminalDisplay,
+        input::{Playback, TerminalKeyboardInput},
+        midi::MidiAudio,
+    },
+    ui::SharedUiState,
 };
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let args = CmdArgs::parse();
+    if let Some(command) = &args.command {
+        match command {
+            Command::Verify { movie, rom } => verify::verify(movie, rom)?,
+            Command::Scenario { scenario, rom } => scenario::run(scenario, rom)?,
+            Command::Retime {
+                movie,
+                output,
+                ticks_per_timer,
+                frames,
+                binary,
+                text,
+            } => {
+                let timebase = if *frames {
+                    Timebase::Frames
+                } else {
+                    Timebase::Cycles
+                };
+                let encoding = match (binary, text) {
+                    (true, _) => Some(MovieEncoding::Binary),
+                    (_, true) => Some(MovieEncoding::Text),
+                    _ => None,
+                };
+                movie::retime(movie, output, timebase, *ticks_per_timer, encoding)?;
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    let rom = fs::read(args.rom)?;
-    let terminal = setup_terminal(args.headless)?;
+    // Required unless there is a subcommand
+    let Some(rom_path) = &args.rom else {
+        unreachable!()
+    };
+    let rom = fs::read(rom_path)?;
+    let config = Config::load(args.config.as_deref())?;
+    let settings = config.resolve(&rom, &args.settings())?;
+    let ui = SharedUiState::default();
+    let rom_hash = rom_hash(&rom);
+    let mut seed = args.random_seed.unwrap_or_else(random);
+    let mut rng_position = 0;
+    let mut clk_freq = settings.clk_freq;
+    let mut ticks_per_timer = settings.ticks_per_timer;
+    let mut fault_policy = args.fault_policy();
+    let mut checkpoint_frames = args.checkpoint_frames.unwrap_or(60);
+    let recording = match &args.input_file {
+        Some(input_file) => {
+            let mut header = MovieHeader::new(rom_hash.clone(), seed, clk_freq);
+            header.ticks_per_timer = ticks_per_timer;
+            header.timebase = args.timebase();
+            header.fault_policy = fault_policy;
+            header.author = args.author.clone();
+            header.comment = args.comment.clone();
+            // Nothing new is recorded without a terminal
+            let recording = movie::open(
+                input_file,
+                args.overwrite,
+                args.read_only || args.headless,
+                args.movie_encoding(),
+                header,
+                args.ticks_per_timer,
+            )?;
 
-    let (inputs, input_writer) = if let Some(input_file) = &args.input_file {
-        if args.overwrite {
-            let writer = Writer::from_path(input_file)?;
-            (vec![], Some(writer))
-        } else {
-            let mut reader = Reader::from_path(input_file)?;
-            let parsed: Vec<(u64, InputEvent)> = reader
-                .deserialize()
-                .map(|result| {
-                    let record: CsvRecord = result?;
-                    let key = Key::try_from(record.key)?;
-                    let kind = InputKind::try_from(record.kind)?;
-                    Ok((record.clk, InputEvent { key, kind }))
-                })
-                .collect::<Result<_>>()?;
-            let f = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(input_file)?;
-            let writer = WriterBuilder::new()
-                .has_headers(parsed.is_empty())
-                .from_writer(f);
-            (parsed, Some(writer))
+            // The movie's settings are needed to replay it faithfully
+            let header = &recording.movie.header;
+            let rules = (!args.fault_rules.is_empty()).then_some(fault_policy);
+            for warning in
+                movie::validate(header, &rom_hash, args.random_seed, args.clk_freq, rules)?
+            {
+                eprintln!("warning: {warning}");
+            }
+            if recording.imported {
+                eprintln!(
+                    "Converted {} to a movie, the original log is kept as {}",
+                    input_file.display(),
+                    movie::backup_path(input_file).display()
+                );
+            }
+            if recording.scripted {
+                eprintln!(
+                    "Replaying input script {}, new input is not recorded",
+                    input_file.display()
+                );
+            }
+            if let Some(recorded) = recording.retimed_from {
+                eprintln!(
+                    "warning: Replaying a movie recorded at {recorded} ticks per timer at {}, \
+                     new input is only recorded after taking over",
+                    recording.ticks_per_timer
+                );
+            }
+            seed = header.seed;
+            rng_position = header.rng_position;
+            fault_policy = header.fault_policy;
+            clk_freq = args.clk_freq.unwrap_or(header.clock_frequency);
+            ticks_per_timer = recording.ticks_per_timer;
+            // A replay applies the events at the start of their frame rather
+            // than where they were recorded, so its state can't match
+            if recording.timebase == Timebase::Frames {
+                if args.checkpoint_frames.is_some_and(|frames| frames > 0) {
+                    eprintln!(
+                        "warning: Frame-timed movies have no checkpoints, \
+                         ignoring --checkpoint-frames"
+                    );
+                }
+                checkpoint_frames = 0;
+            }
+            Some(recording)
         }
-    } else {
-        (vec![], None)
+        None => None,
+    };
+    let seeded_rng = ChaCha12Rng::seed_from_u64(seed);
+    let cpu = SimpleCpu::new(clk_freq, seeded_rng)
+        .with_ticks_per_timer(ticks_per_timer)
+        .with_rng_position(rng_position);
+    let (inputs, resets, start) = match &recording {
+        Some(recording) => (
+            recording.movie.events.clone(),
+            recording.movie.resets.clone(),
+            recording.movie.start.clone(),
+        ),
+        None => (vec![], vec![], None),
     };
+    let mut crash_header = MovieHeader::new(rom_hash, seed, clk_freq);
+    crash_header.ticks_per_timer = ticks_per_timer;
+    crash_header.fault_policy = fault_policy;
+    let mut chip8 = Chip8::new(cpu, inputs)
+        .with_resets(resets)
+        .with_stop_conditions(args.stop_conditions(ticks_per_timer))
+        .with_fault_policy(fault_policy);
+    chip8.load(&rom)?;
+    if let Some(state) = &start {
+        chip8.load_state(state)?;
+    }
+    let midi_driver = args
+        .midi_file
+        .clone()
+        .map(|midi_file| MidiAudio::new(midi_file, ticks_per_timer))
+        .transpose()?;
 
-    let input_driver = TerminalKeyboardInput::new(input_writer);
-    let display_driver = {
-        if !args.headless {
-            Some(TerminalDisplay::new(
-                terminal,
-                args.refresh_rate,
-                args.bg_color,
-                args.fg_color,
-                args.border_color,
-            ))
-        } else {
-            None
+    if args.headless {
+        if fault_policy
+            .rules()
+            .iter()
+            .any(|(_, action)| *action == FaultAction::Pause)
+        {
+            bail!("--on-fault pause needs the terminal to resume from");
         }
-    };
-    let audio_driver = {
-        if !args.headless {
-            Some(TerminalAudio::default())
-        } else {
-            None
+        if args.stop_conditions(ticks_per_timer).is_empty() {
+            eprintln!("warning: No stop condition, running until killed");
         }
+        let audio_driver = midi_driver.map(|midi| Box::new(midi) as Box<dyn AudioDriver>);
+        let mut chip8 = chip8.with_turbo();
+        let reason = headless::run(&mut chip8, audio_driver, args.report, ticks_per_timer).await?;
+        crash::report_suppressed(&chip8)?;
+        crash::report(&mut chip8, &reason, rom_path, crash_header)?;
+        return Ok(exit_code(&reason));
+    }
+
+    let (terminal, capabilities) = setup_terminal(args.keypad)?;
+
+    let (input_writer, playback) = match (recording, &args.input_file) {
+        (Some(recording), Some(input_file)) => {
+            let mut ui = ui.checked_write()?;
+            let movie = &recording.movie;
+            ui.playback_end = movie
+                .events
+                .last()
+                .map(|(clk, _)| *clk)
+                .max(movie.resets.last().copied());
+            ui.read_only = args.read_only;
+
+            // Taking over would overwrite the script with a movie
+            let path = input_file.clone();
+            let playback = (!recording.scripted).then(|| Playback {
+                movie: recording.movie,
+                encoding: recording.encoding,
+                timebase: recording.timebase,
+                create: Box::new(move || File::create(&path)),
+            });
+            (recording.writer, playback)
+        }
+        _ => (None, None),
+    };
+
+    let mut input_driver = TerminalKeyboardInput::new(input_writer)
+        .with_keymap(settings.keymap.clone())
+        .with_ticks_per_timer(ticks_per_timer)
+        .with_ui_state(ui.clone())
+        .with_typed_ahead(capabilities.typed_ahead);
+    if let Some(playback) = playback {
+        input_driver = input_driver.with_playback(playback);
+    }
+    if !capabilities.key_release {
+        input_driver = input_driver.with_synthesized_releases(settings.key_hold);
+    }
+    let display_driver = TerminalDisplay::new(terminal, settings.refresh_rate)
+        .with_palette(settings.palette)
+        .with_border_color(settings.border_color)
+        .with_render_mode(settings.render_mode, capabilities.graphics)
+        .with_filter(args.filter_mode())
+        .with_status_panel(args.status_panel)
+        .with_keypad(args.keypad)
+        .with_keymap(settings.keymap)
+        .with_ui_state(ui);
+    let audio_driver: Box<dyn AudioDriver> = match midi_driver {
+        Some(midi) => Box::new(midi),
+        None => Box::new(TerminalAudio::default()),
     };
 
-    let seeded_rng = StdRng::seed_from_u64(args.random_seed.unwrap_or(random()));
-    let cpu = SimpleCpu::new(args.clk_freq, seeded_rng);
-    let mut chip8 = Chip8::new(cpu, inputs);
-    let res = chip8
-        .load_and_run(rom.as_slice(), input_driver, display_driver, audio_driver)
+    if args.input_file.is_some() {
+        chip8 = chip8.with_checkpoints(checkpoint_frames);
+    }
+    if let Some(clk) = args.fast_forward {
+        chip8 = chip8.with_fast_forward(clk);
+    }
+    let reason = chip8
+        .run(input_driver, Some(display_driver), Some(audio_driver))
         .await;
 
-    restore_terminal(args.headless)?;
-    res?;
+    restore_terminal()?;
+    if !matches!(reason, ExitReason::UserQuit) {
+        eprintln!("{reason}");
+    }
+    crash::report_suppressed(&chip8)?;
+    crash::report(&mut chip8, &reason, rom_path, crash_header)?;
+    Ok(exit_code(&reason))
+}
 
-    Ok(())
+/// Process exit status for each way a run can end, so that scripts can tell
+/// a clean stop from a crash. 1 is left for other errors and 2 for usage.
+fn exit_code(reason: &ExitReason) -> ExitCode {
+    let code = match reason {
+        ExitReason::UserQuit => 0,
+        ExitReason::Halted => 3,
+        ExitReason::WaitingForInput => 4,
+        ExitReason::CycleLimit => 5,
+        ExitReason::ReachedPc(_) => 6,
+        ExitReason::Fault { .. } => 70,
+    };
+    ExitCode::from(code)
 }
@@ -0,0 +1,268 @@
+use chip8_core::{
+    constants::TICKS_PER_TIMER,
+    fault::FaultPolicy,
+    input::{InputEvent, InputKind},
+    keypad::Key,
+    movie::{Movie, MovieEncoding, MovieHeader, MovieWriter, Timebase, EMULATOR, PLATFORM},
+    script::{self, Timing},
+};
+use csv::Reader;
+use eyre::{bail, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+/// Row of the input logs written before movies existed.
+#[derive(Serialize, Deserialize)]
+pub struct CsvRecord {
+    pub clk: u64,
+    pub key: char,
+    pub kind: u8,
+}
+
+/// Movie replayed at startup, and where the new input goes.
+pub struct Recording {
+    /// Keyed by the clk the events are replayed at
+    pub movie: Movie,
+    /// None when replaying read-only
+    pub writer: Option<MovieWriter<File>>,
+    /// Encoding of the file, used again when the user takes over
+    pub encoding: MovieEncoding,
+    /// Timebase of the file, likewise
+    pub timebase: Timebase,
+    /// Cycles per frame to replay at
+    pub ticks_per_timer: u64,
+    /// Ticks per timer of a cycle-timed movie replayed at another rate. It is
+    /// not appended to, as that would mix two timings, until taken over.
+    pub retimed_from: Option<u64>,
+    /// Set when the movie came from an older CSV log
+    pub imported: bool,
+    /// Set when the movie came from an input script, which is never written
+    pub scripted: bool,
+}
+
+/// Opens `path` for replay and recording. Existing movies are replayed and
+/// appended to, unless `overwrite` is set, or only replayed if `read_only` is.
+/// A legacy CSV log is converted in place to a movie with `header`, keeping the
+/// original next to it (see [`backup_path`]), and an
+/// input script is replayed as one, timed with the clock in `header`.
+///
+/// Events are replayed at `ticks_per_timer` if given, else at the movie's if
+/// it is timed in cycles, else at the one in `header`.
+pub fn open(
+    path: &Path,
+    overwrite: bool,
+    read_only: bool,
+    encoding: MovieEncoding,
+    header: MovieHeader,
+    ticks_per_timer: Option<u64>,
+) -> Result<Recording> {
+    if overwrite || (!read_only && !path.exists()) {
+        let file = File::create(path)?;
+        let writer = MovieWriter::new(file, encoding, &header, None)?;
+        return Ok(Recording {
+            timebase: header.timebase,
+            ticks_per_timer: header.ticks_per_timer,
+            movie: Movie::new(header),
+            writer: Some(writer),
+            encoding,
+            retimed_from: None,
+            imported: false,
+            scripted: false,
+        });
+    }
+
+    let bytes = fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    let scripted = script::detect(&bytes);
+    let (mut movie, mut writer, encoding, imported) = match MovieEncoding::detect(&bytes) {
+        Some(encoding) => {
+            let movie = Movie::decode(&bytes)
+                .wrap_err_with(|| format!("Invalid movie {}", path.display()))?;
+            (movie, None, encoding, false)
+        }
+        None if scripted => {
+            let timing = Timing {
+                clk_freq: header.clock_frequency,
+                ticks_per_timer: header.ticks_per_timer,
+            };
+            let source = String::from_utf8_lossy(&bytes);
+            let mut movie = Movie::new(header.clone());
+            movie.events = script::parse(&source, timing)
+                .wrap_err_with(|| format!("Invalid input script {}", path.display()))?;
+            (movie, None, encoding, false)
+        }
+        None => {
+            // Input logs predate configurable ticks per timer
+            let mut movie = Movie::new(MovieHeader {
+                ticks_per_timer: TICKS_PER_TIMER,
+                timebase: Timebase::Cycles,
+                ..header.clone()
+            });
+            movie.events = import_csv(&bytes)
+                .wrap_err_with(|| format!("Invalid input log {}", path.display()))?;
+            if !read_only {
+                convert(path, &movie, encoding)
+                    .wrap_err_with(|| format!("Failed to convert {}", path.display()))?;
+            }
+            // Appended to below like any other movie
+            (movie, None, encoding, !read_only)
+        }
+    };
+
+    let timebase = movie.header.timebase;
+    let recorded = movie.header.ticks_per_timer;
+    let ticks_per_timer = ticks_per_timer.unwrap_or(match timebase {
+        Timebase::Cycles => recorded,
+        Timebase::Frames => header.ticks_per_timer,
+    });
+    let retimed_from =
+        (timebase == Timebase::Cycles && ticks_per_timer != recorded).then_some(recorded);
+    if timebase == Timebase::Frames {
+        // New events are written in frames of the rate they are recorded at
+        movie.header.ticks_per_timer = ticks_per_timer;
+    }
+    if retimed_from.is_some() {
+        writer = None;
+    } else if !read_only && !scripted {
+        let file = OpenOptions::new().append(true).open(path)?;
+        writer = Some(MovieWriter::append(file, encoding, &movie));
+    }
+
+    Ok(Recording {
+        movie: movie.retimed(Timebase::Cycles, ticks_per_timer),
+        writer,
+        encoding,
+        timebase,
+        ticks_per_timer,
+        retimed_from,
+        imported,
+        scripted,
+    })
+}
+
+/// Where the original of a converted CSV log is kept: `<path>.bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+// Writes `movie` next to the CSV log at `path` first, so that a failed write
+// leaves the log untouched, then moves the log to its backup and the movie in
+// its place
+fn convert(path: &Path, movie: &Movie, encoding: MovieEncoding) -> Result<()> {
+    let mut converted = path.as_os_str().to_owned();
+    converted.push(".tmp");
+    let converted = PathBuf::from(converted);
+
+    let mut writer = MovieWriter::new(File::create(&converted)?, encoding, &movie.header, None)?;
+    for &(clk, event) in &movie.events {
+        writer.write_event(clk, event)?;
+    }
+    drop(writer);
+
+    fs::rename(path, backup_path(path))?;
+    fs::rename(&converted, path)?;
+    Ok(())
+}
+
+/// Converts the movie at `input` to `timebase` at `ticks_per_timer`, by
+/// default the movie's own, and writes it to `output`.
+pub fn retime(
+    input: &Path,
+    output: &Path,
+    timebase: Timebase,
+    ticks_per_timer: Option<u64>,
+    encoding: Option<MovieEncoding>,
+) -> Result<()> {
+    let bytes = fs::read(input)?;
+    let movie =
+        Movie::decode(&bytes).wrap_err_with(|| format!("Invalid movie {}", input.display()))?;
+    let ticks_per_timer = ticks_per_timer.unwrap_or(movie.header.ticks_per_timer);
+    if ticks_per_timer == 0 {
+        bail!("Ticks per timer must be positive");
+    }
+
+    let retimed = movie.retimed(timebase, ticks_per_timer);
+    if retimed.checkpoints.len() < movie.checkpoints.len() {
+        eprintln!(
+            "warning: dropped {} checkpoints, which only hold at the recorded timing",
+            movie.checkpoints.len()
+        );
+    }
+    let encoding = encoding
+        .or(MovieEncoding::detect(&bytes))
+        .unwrap_or(MovieEncoding::Text);
+    fs::write(output, retimed.encode(encoding))
+        .wrap_err_with(|| format!("Failed to write {}", output.display()))?;
+    println!(
+        "{}: {} events retimed to {ticks_per_timer} ticks per timer",
+        output.display(),
+        retimed.events.len()
+    );
+    Ok(())
+}
+
+fn import_csv(bytes: &[u8]) -> Result<Vec<(u64, InputEvent)>> {
+    Reader::from_reader(bytes)
+        .deserialize()
+        .map(|result| {
+            let record: CsvRecord = result?;
+            let key = Key::try_from(record.key)?;
+            let kind = InputKind::try_from(record.kind)?;
+            Ok((record.clk, InputEvent { key, kind }))
+        })
+        .collect()
+}
+
+/// Refuses movies made for another ROM, and lists the settings of this run
+/// that differ from the movie's.
+pub fn validate(
+    header: &MovieHeader,
+    rom_hash: &str,
+    seed: Option<u64>,
+    clk_freq: Option<u64>,
+    fault_policy: Option<FaultPolicy>,
+) -> Result<Vec<String>> {
+    if header.rom_hash != rom_hash {
+        bail!(
+            "Movie was recorded with ROM {}, not {rom_hash}",
+            header.rom_hash
+        );
+    }
+
+    let mut warnings = vec![];
+    if header.platform != PLATFORM {
+        warnings.push(format!(
+            "Movie was recorded for platform {}, running as {PLATFORM}",
+            header.platform
+        ));
+    }
+    if header.emulator != EMULATOR {
+        warnings.push(format!(
+            "Movie was recorded with {}, replaying with {EMULATOR}",
+            header.emulator
+        ));
+    }
+    if let Some(seed) = seed.filter(|seed| *seed != header.seed) {
+        warnings.push(format!(
+            "Ignoring random seed {seed}, the movie was recorded with {}",
+            header.seed
+        ));
+    }
+    if let Some(clk_freq) = clk_freq.filter(|freq| *freq != header.clock_frequency) {
+        warnings.push(format!(
+            "Running at {clk_freq}Hz, the movie was recorded at {}Hz",
+            header.clock_frequency
+        ));
+    }
+    if let Some(policy) = fault_policy.filter(|policy| *policy != header.fault_policy) {
+        warnings.push(format!(
+            "Ignoring --on-fault {policy}, the movie was recorded with {}",
+            header.fault_policy
+        ));
+    }
+    Ok(warnings)
+}
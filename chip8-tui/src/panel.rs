@@ -0,0 +1,70 @@
+use chip8_core::{
+    constants::NUM_REGISTERS, drivers::DisplayFrame, keypad::KEYPAD_LAYOUT, state::StateSnapshot,
+};
+use ratatui::{
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+};
+
+pub const PANEL_WIDTH: u16 = 22;
+pub const PANEL_HEIGHT: u16 = 23;
+
+/// Side panel with the machine state and frame statistics.
+pub fn status_panel(frame: &DisplayFrame, border_color: Color) -> Paragraph<'static> {
+    let DisplayFrame { state, stats, .. } = frame;
+
+    let mut lines = vec![
+        Line::from(format!(
+            "PC {:04X}   I {:04X}",
+            state.program_counter, state.index_register
+        )),
+        Line::from(format!(
+            "SP {:X}  DT {:02X}  ST {:02X}",
+            state.stack_pointer, state.delay_timer, state.sound_timer
+        )),
+        Line::default(),
+    ];
+
+    let half = NUM_REGISTERS / 2;
+    for i in 0..half {
+        lines.push(Line::from(format!(
+            "V{:X} {:02X}      V{:X} {:02X}",
+            i,
+            state.registers[i],
+            i + half,
+            state.registers[i + half]
+        )));
+    }
+    lines.push(Line::default());
+
+    lines.extend(keypad_lines(state));
+    lines.push(Line::default());
+
+    lines.extend([
+        Line::from(format!("CPU {}/{}Hz", stats.cpu_freq, stats.target_freq)),
+        Line::from(format!("FPS {:.1}", stats.fps)),
+        Line::from(format!("Instr/frame {}", stats.instructions)),
+        Line::from(format!("Draws/frame {}", stats.draws)),
+    ]);
+
+    Paragraph::new(lines).block(Block::bordered().title("Status").fg(border_color))
+}
+
+// Keys held as the CPU sees them are shown reversed
+fn keypad_lines(state: &StateSnapshot) -> Vec<Line<'static>> {
+    KEYPAD_LAYOUT
+        .iter()
+        .map(|row| {
+            let spans = row.iter().flat_map(|&key| {
+                let style = if state.keypad[key as usize] {
+                    Style::new().reversed()
+                } else {
+                    Style::new()
+                };
+                [Span::styled(format!(" {key} "), style), Span::raw(" ")]
+            });
+            Line::from(spans.collect::<Vec<_>>())
+        })
+        .collect()
+}
@@ -0,0 +1,325 @@
+use chip8_core::filter::MAX_INTENSITY;
+use clap::ValueEnum;
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span, Text},
+};
+use serde::Deserialize;
+use std::array;
+
+// Braille uses the fewest cells: 2x4 pixels per cell
+const MIN_CELL_WIDTH: usize = 2;
+const MIN_CELL_HEIGHT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderMode {
+    /// Largest mode that fits the terminal, re-evaluated on resize
+    Auto,
+    /// Integer-scaled full blocks, two cells wide per pixel
+    Full,
+    /// Upper/lower half blocks, 1x2 pixels per cell
+    HalfBlock,
+    /// Quadrant blocks, 2x2 pixels per cell
+    Quadrant,
+    /// Braille dots, 2x4 pixels per cell
+    Braille,
+    /// Bitmap through the kitty graphics protocol
+    Kitty,
+    /// Bitmap through Sixel
+    Sixel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    Full(u16),
+    HalfBlock,
+    Quadrant,
+    Braille,
+}
+
+impl Renderer {
+    /// Picks a renderer for `mode` given the cells available for the picture.
+    pub fn select(mode: RenderMode, width: u16, height: u16, pixels: (usize, usize)) -> Self {
+        let full_scale = Self::max_full_scale(width, height, pixels);
+        match mode {
+            RenderMode::Full => Self::Full(full_scale.unwrap_or(1)),
+            RenderMode::HalfBlock => Self::HalfBlock,
+            RenderMode::Quadrant => Self::Quadrant,
+            RenderMode::Braille => Self::Braille,
+            // Used when no bitmap protocol is available
+            RenderMode::Auto | RenderMode::Kitty | RenderMode::Sixel => {
+                full_scale.map(Self::Full).unwrap_or_else(|| {
+                    [Self::HalfBlock, Self::Quadrant]
+                        .into_iter()
+                        .find(|renderer| {
+                            let (w, h) = renderer.size(pixels);
+                            w <= width && h <= height
+                        })
+                        .unwrap_or(Self::Braille)
+                })
+            }
+        }
+    }
+
+    fn max_full_scale(
+        width: u16,
+        height: u16,
+        (px_width, px_height): (usize, usize),
+    ) -> Option<u16> {
+        let scale = (width as usize / (2 * px_width)).min(height as usize / px_height);
+        (scale > 0).then_some(scale as u16)
+    }
+
+    /// Number of cells needed to draw a picture of `pixels` (width, height).
+    pub fn size(&self, (width, height): (usize, usize)) -> (u16, u16) {
+        let (w, h) = match self {
+            Self::Full(scale) => (2 * width * *scale as usize, height * *scale as usize),
+            Self::HalfBlock => (width, height.div_ceil(2)),
+            Self::Quadrant => (width.div_ceil(2), height.div_ceil(2)),
+            Self::Braille => (width.div_ceil(2), height.div_ceil(4)),
+        };
+        (w as u16, h as u16)
+    }
+
+    pub fn render<R: AsRef<[u8]>>(&self, intensities: &[R], palette: &Palette) -> Text<'static> {
+        let height = intensities.len();
+        let width = intensities.first().map_or(0, |row| row.as_ref().len());
+        let pixel = |x: usize, y: usize| {
+            if y < height && x < width {
+                intensities[y].as_ref()[x]
+            } else {
+                0
+            }
+        };
+        // Cells holding several pixels can only have one color, the brightest
+        let shade = |dots: &[(usize, usize)], x: usize, y: usize| {
+            let max = dots.iter().map(|&(dx, dy)| pixel(x + dx, y + dy)).max();
+            Style::new().fg(palette.shade(max.unwrap_or_default()))
+        };
+
+        let lines = match self {
+            Self::Full(scale) => {
+                let scale = *scale as usize;
+                render_cells(width, height, 1, 1, |x, y| {
+                    let style = Style::new().fg(palette.shade(pixel(x, y)));
+                    ("██".repeat(scale), style)
+                })
+                .into_iter()
+                .flat_map(|line| std::iter::repeat_n(line, scale))
+                .collect()
+            }
+            Self::HalfBlock => render_cells(width, height, 1, 2, |x, y| {
+                let style = Style::new()
+                    .fg(palette.shade(pixel(x, y)))
+                    .bg(palette.shade(pixel(x, y + 1)));
+                ("▀".to_string(), style)
+            }),
+            Self::Quadrant => render_cells(width, height, 2, 2, |x, y| {
+                let bits = QUADRANT_DOTS
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &(dx, dy))| pixel(x + dx, y + dy) > 0)
+                    .fold(0, |acc, (bit, _)| acc | 1 << bit);
+                (QUADRANTS[bits].to_string(), shade(&QUADRANT_DOTS, x, y))
+            }),
+            Self::Braille => render_cells(width, height, 2, 4, |x, y| {
+                let bits = BRAILLE_DOTS
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &(dx, dy))| pixel(x + dx, y + dy) > 0)
+                    .fold(0, |acc, (bit, _)| acc | 1 << bit);
+                let c = char::from_u32(0x2800 + bits).unwrap_or(' ');
+                (c.to_string(), shade(&BRAILLE_DOTS, x, y))
+            }),
+        };
+
+        Text::from(lines)
+    }
+}
+
+/// Foreground and background colors, with the shades in between used for
+/// partially lit pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub fg: Color,
+    pub bg: Color,
+}
+
+impl Palette {
+    pub fn shade(&self, intensity: u8) -> Color {
+        match intensity {
+            0 => self.bg,
+            MAX_INTENSITY => self.fg,
+            _ => {
+                let [r, g, b] = self.shade_rgb(intensity);
+                Color::Rgb(r, g, b)
+            }
+        }
+    }
+
+    pub fn shade_rgb(&self, intensity: u8) -> [u8; 3] {
+        let (fg, bg) = (rgb(self.fg), rgb(self.bg));
+        array::from_fn(|i| {
+            let delta = (fg[i] as i32 - bg[i] as i32) * intensity as i32 / MAX_INTENSITY as i32;
+            (bg[i] as i32 + delta) as u8
+        })
+    }
+}
+
+/// Named color schemes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PalettePreset {
+    /// White on black
+    Classic,
+    /// Amber monochrome monitor
+    Amber,
+    /// Green phosphor monitor
+    Green,
+    /// Original Game Boy screen
+    Lcd,
+    /// Black on white
+    Paper,
+}
+
+impl PalettePreset {
+    pub fn palette(&self) -> Palette {
+        let (fg, bg) = match self {
+            Self::Classic => (Color::White, Color::Black),
+            Self::Amber => (Color::Rgb(255, 176, 0), Color::Rgb(40, 20, 0)),
+            Self::Green => (Color::Rgb(51, 255, 51), Color::Rgb(0, 24, 0)),
+            Self::Lcd => (Color::Rgb(15, 56, 15), Color::Rgb(155, 188, 15)),
+            Self::Paper => (Color::Black, Color::White),
+        };
+        Palette { fg, bg }
+    }
+}
+
+// (dx, dy) of each quadrant, in the bit order of QUADRANTS
+const QUADRANT_DOTS: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+// Indexed by top-left | top-right << 1 | bottom-left << 2 | bottom-right << 3
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+// (dx, dy) of each dot in a braille cell, in the bit order of the Unicode block
+const BRAILLE_DOTS: [(usize, usize); 8] = [
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (1, 0),
+    (1, 1),
+    (1, 2),
+    (0, 3),
+    (1, 3),
+];
+
+fn render_cells(
+    width: usize,
+    height: usize,
+    cell_width: usize,
+    cell_height: usize,
+    cell: impl Fn(usize, usize) -> (String, Style),
+) -> Vec<Line<'static>> {
+    (0..height.div_ceil(cell_height))
+        .map(|row| {
+            // Adjacent cells with the same style are merged into one span
+            let mut spans: Vec<(String, Style)> = vec![];
+            for col in 0..width.div_ceil(cell_width) {
+                let (symbol, style) = cell(col * cell_width, row * cell_height);
+                match spans.last_mut() {
+                    Some((content, last)) if *last == style => content.push_str(&symbol),
+                    _ => spans.push((symbol, style)),
+                }
+            }
+            Line::from(
+                spans
+                    .into_iter()
+                    .map(|(content, style)| Span::styled(content, style))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect()
+}
+
+/// Smallest terminal that can show a picture of `pixels` (width, height) with a border.
+pub fn min_terminal_size((width, height): (usize, usize)) -> (u16, u16) {
+    (
+        (width.div_ceil(MIN_CELL_WIDTH) + 2) as u16,
+        (height.div_ceil(MIN_CELL_HEIGHT) + 2) as u16,
+    )
+}
+
+/// Centers a `width`x`height` rectangle in `area`, clipped to it.
+pub fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Approximate RGB value of a terminal color, using xterm's defaults.
+pub fn rgb(color: Color) -> [u8; 3] {
+    match color {
+        Color::Reset | Color::Black => [0, 0, 0],
+        Color::Red => [205, 0, 0],
+        Color::Green => [0, 205, 0],
+        Color::Yellow => [205, 205, 0],
+        Color::Blue => [0, 0, 238],
+        Color::Magenta => [205, 0, 205],
+        Color::Cyan => [0, 205, 205],
+        Color::Gray => [229, 229, 229],
+        Color::DarkGray => [127, 127, 127],
+        Color::LightRed => [255, 0, 0],
+        Color::LightGreen => [0, 255, 0],
+        Color::LightYellow => [255, 255, 0],
+        Color::LightBlue => [92, 92, 255],
+        Color::LightMagenta => [255, 0, 255],
+        Color::LightCyan => [0, 255, 255],
+        Color::White => [255, 255, 255],
+        Color::Rgb(r, g, b) => [r, g, b],
+        Color::Indexed(i) => indexed_rgb(i),
+    }
+}
+
+fn indexed_rgb(i: u8) -> [u8; 3] {
+    const NAMED: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::Gray,
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightMagenta,
+        Color::LightCyan,
+        Color::White,
+    ];
+    match i {
+        0..=15 => rgb(NAMED[i as usize]),
+        // 6x6x6 color cube
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + 40 * v };
+            let i = i - 16;
+            [level(i / 36), level((i / 6) % 6), level(i % 6)]
+        }
+        // Grayscale ramp
+        _ => {
+            let v = 8 + 10 * (i - 232);
+            [v, v, v]
+        }
+    }
+}
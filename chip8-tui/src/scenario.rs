@@ -0,0 +1,42 @@
+use chip8_core::scenario::Scenario;
+use eyre::{bail, Result, WrapErr};
+use std::{fs, path::Path};
+
+/// Runs the scenario at `path` against the ROM at `rom_path`, failing if any
+/// expectation isn't met.
+pub fn run(path: &Path, rom_path: &Path) -> Result<()> {
+    let rom = fs::read(rom_path)?;
+    let source = fs::read_to_string(path)?;
+    let scenario = Scenario::parse(&source)
+        .wrap_err_with(|| format!("Invalid scenario {}", path.display()))?;
+
+    // Golden files are next to the scenario
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let report = scenario
+        .run(&rom, dir)
+        .wrap_err_with(|| format!("Scenario {} stopped", path.display()))?;
+
+    for failure in &report.failures {
+        eprintln!(
+            "{}:{}: at clk {}: {}",
+            path.display(),
+            failure.line,
+            failure.clk,
+            failure.message
+        );
+    }
+    if !report.passed() {
+        bail!(
+            "{} of {} expectations failed",
+            report.failures.len(),
+            report.checks
+        );
+    }
+    println!(
+        "{}: {} expectations passed over {} cycles",
+        path.display(),
+        report.checks,
+        report.clk
+    );
+    Ok(())
+}
@@ -1,47 +1,239 @@
 use chip8_core::constants::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use crossterm::{
     cursor::{Hide, Show},
-    event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
 use eyre::{bail, Result};
 use ratatui::{backend::CrosstermBackend, layout::Rect, Terminal};
-use std::io::{stdout, Error, Stdout};
+use std::{
+    io::{stdout, Error, Stdout, Write},
+    time::Duration,
+};
+
+use crate::{
+    graphics::{GraphicsProtocol, GRAPHICS_QUERY},
+    render::min_terminal_size,
+};
+
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Optional terminal features detected at startup.
+pub struct Capabilities {
+    pub graphics: Option<GraphicsProtocol>,
+    /// Whether key release events are reported
+    pub key_release: bool,
+    /// Keys typed while the terminal was being queried
+    pub typed_ahead: Vec<KeyEvent>,
+}
 
-pub fn setup_terminal(headless: bool) -> Result<Terminal<CrosstermBackend<Stdout>>> {
+/// `mouse` enables mouse reporting, which disables text selection.
+pub fn setup_terminal(mouse: bool) -> Result<(Terminal<CrosstermBackend<Stdout>>, Capabilities)> {
     let backend = CrosstermBackend::new(stdout());
     let terminal = Terminal::new(backend)?;
 
-    if !headless {
-        enable_raw_mode()?;
-        execute!(stdout(), EnterAlternateScreen, Hide)?;
-        execute!(
-            stdout(),
-            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES),
-        )?;
-
-        // Check terminal size
-        let Rect { width, height, .. } = terminal.size()?;
-        if width < 2 * DISPLAY_WIDTH as u16 {
-            bail!(
-                "Error: Terminal width {width} less than minimum width {}",
-                2 * DISPLAY_WIDTH,
-            );
-        } else if height < DISPLAY_HEIGHT as u16 {
-            bail!("Error: Terminal height {height} less than minimum height {DISPLAY_HEIGHT}");
+    // Check terminal size
+    let Rect { width, height, .. } = terminal.size()?;
+    let (min_width, min_height) = min_terminal_size((DISPLAY_WIDTH, DISPLAY_HEIGHT));
+    if width < min_width {
+        bail!("Error: Terminal width {width} less than minimum width {min_width}");
+    } else if height < min_height {
+        bail!("Error: Terminal height {height} less than minimum height {min_height}");
+    }
+
+    enable_raw_mode()?;
+    match setup_modes(mouse) {
+        Ok(capabilities) => Ok((terminal, capabilities)),
+        Err(e) => {
+            restore_terminal()?;
+            Err(e)
+        }
+    }
+}
+
+fn setup_modes(mouse: bool) -> Result<Capabilities> {
+    execute!(stdout(), EnterAlternateScreen, Hide)?;
+    // Queried before enabling other reports so that keys typed meanwhile
+    // arrive in the legacy encoding
+    let (graphics, typed_ahead) = detect_graphics()?;
+    execute!(
+        stdout(),
+        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES),
+    )?;
+    if mouse {
+        execute!(stdout(), EnableMouseCapture)?;
+    }
+
+    // Terminals that don't answer are assumed to lack the kitty protocol
+    Ok(Capabilities {
+        graphics,
+        key_release: supports_keyboard_enhancement().unwrap_or(false),
+        typed_ahead,
+    })
+}
+
+pub fn restore_terminal() -> Result<(), Error> {
+    execute!(stdout(), DisableMouseCapture, PopKeyboardEnhancementFlags)?;
+    execute!(stdout(), Show, LeaveAlternateScreen)?;
+    disable_raw_mode()
+}
+
+fn detect_graphics() -> Result<(Option<GraphicsProtocol>, Vec<KeyEvent>)> {
+    let mut stdout = stdout();
+    write!(stdout, "{GRAPHICS_QUERY}")?;
+    stdout.flush()?;
+
+    let (response, typed) = split_response(&read_response(QUERY_TIMEOUT)?);
+    Ok((
+        GraphicsProtocol::detect(&String::from_utf8_lossy(&response)),
+        typed_keys(&typed),
+    ))
+}
+
+// Separates the answers to the query from whatever else was read, which is
+// input from the user
+fn split_response(bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut response = vec![];
+    let mut typed = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        let len = if rest.starts_with(b"\x1b_G") {
+            find(rest, b"\x1b\\").map(|end| end + 2)
+        } else if rest.starts_with(b"\x1b[?") {
+            find(rest, b"c").map(|end| end + 1)
+        } else {
+            typed.push(bytes[i]);
+            i += 1;
+            continue;
         }
+        .unwrap_or(rest.len());
+        response.extend_from_slice(&rest[..len]);
+        i += len;
     }
+    (response, typed)
+}
 
-    Ok(terminal)
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes
+        .windows(needle.len())
+        .position(|window| window == needle)
 }
 
-pub fn restore_terminal(headless: bool) -> Result<(), Error> {
-    if !headless {
-        execute!(stdout(), PopKeyboardEnhancementFlags)?;
-        execute!(stdout(), Show, LeaveAlternateScreen)?;
-        disable_raw_mode()?;
+// Each key is pressed and released at once, as the release, if reported at
+// all, was lost. Escape sequences such as arrow keys are dropped.
+fn typed_keys(bytes: &[u8]) -> Vec<KeyEvent> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars().peekable();
+    let mut keys = vec![];
+    while let Some(c) = chars.next() {
+        let (code, modifiers) = match c {
+            '\x1b' => match chars.peek() {
+                Some('[') => {
+                    chars.next();
+                    // Parameters and intermediates up to the final byte
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                Some('O') => {
+                    chars.next();
+                    chars.next();
+                    continue;
+                }
+                _ => (KeyCode::Esc, KeyModifiers::NONE),
+            },
+            '\r' | '\n' => (KeyCode::Enter, KeyModifiers::NONE),
+            '\t' => (KeyCode::Tab, KeyModifiers::NONE),
+            '\x08' | '\x7f' => (KeyCode::Backspace, KeyModifiers::NONE),
+            '\x01'..='\x1a' => (
+                KeyCode::Char((c as u8 + b'a' - 1) as char),
+                KeyModifiers::CONTROL,
+            ),
+            c if c.is_control() => continue,
+            c => (KeyCode::Char(c), KeyModifiers::NONE),
+        };
+        for kind in [KeyEventKind::Press, KeyEventKind::Release] {
+            keys.push(KeyEvent::new_with_kind(code, modifiers, kind));
+        }
     }
+    keys
+}
 
-    Ok(())
+// Reads straight from the file descriptor so that no bytes are left behind in
+// a userspace buffer that crossterm's event reader would not see.
+#[cfg(unix)]
+fn read_response(timeout: Duration) -> Result<Vec<u8>> {
+    use std::time::Instant;
+
+    let start = Instant::now();
+    let mut response = vec![];
+    let mut buf = [0u8; 256];
+    while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fds` is a valid pollfd array of length 1
+        let ready = unsafe { libc::poll(&mut fds, 1, remaining.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            break;
+        }
+        // SAFETY: `buf` is valid for writes of `buf.len()` bytes
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n as usize]);
+
+        if GraphicsProtocol::is_complete(&String::from_utf8_lossy(&response)) {
+            break;
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(not(unix))]
+fn read_response(_timeout: Duration) -> Result<Vec<u8>> {
+    Ok(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_keys_typed_around_the_response() {
+        let (response, typed) = split_response(b"q\x1b_Gi=31;OK\x1b\\w\x1b[?62;4c\x1b[Ae\x03\x1b");
+        assert_eq!(response, b"\x1b_Gi=31;OK\x1b\\\x1b[?62;4c");
+        assert_eq!(typed, b"qw\x1b[Ae\x03\x1b");
+
+        let presses = typed_keys(&typed)
+            .into_iter()
+            .filter(|key| key.kind == KeyEventKind::Press)
+            .map(|key| (key.code, key.modifiers))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            presses,
+            [
+                (KeyCode::Char('q'), KeyModifiers::NONE),
+                (KeyCode::Char('w'), KeyModifiers::NONE),
+                (KeyCode::Char('e'), KeyModifiers::NONE),
+                (KeyCode::Char('c'), KeyModifiers::CONTROL),
+                (KeyCode::Esc, KeyModifiers::NONE),
+            ]
+        );
+    }
 }
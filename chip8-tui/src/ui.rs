@@ -0,0 +1,17 @@
+use chip8_core::keypad::Key;
+use ratatui::layout::Rect;
+use std::sync::{Arc, RwLock};
+
+/// Frontend state shared between the input and display drivers.
+#[derive(Debug, Default)]
+pub struct UiState {
+    pub show_help: bool,
+    /// On-screen keypad buttons as last drawn, empty when hidden
+    pub keypad_buttons: Vec<(Rect, Key)>,
+    /// Last clk of the movie being replayed, until the user takes over
+    pub playback_end: Option<u64>,
+    /// Live input is ignored while replaying read-only
+    pub read_only: bool,
+}
+
+pub type SharedUiState = Arc<RwLock<UiState>>;
@@ -0,0 +1,152 @@
+use chip8_core::{cpu::SimpleCpu, movie::Movie, Chip8};
+use eyre::{bail, Result, WrapErr};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use std::{fs, path::Path};
+
+use crate::{config::rom_hash, movie};
+
+/// Replays `movie_path` as fast as possible and compares the state with every
+/// checkpoint in it.
+pub fn verify(movie_path: &Path, rom_path: &Path) -> Result<()> {
+    let rom = fs::read(rom_path)?;
+    let movie = Movie::decode(&fs::read(movie_path)?)
+        .wrap_err_with(|| format!("Invalid movie {}", movie_path.display()))?;
+    for warning in movie::validate(&movie.header, &rom_hash(&rom), None, None, None)? {
+        eprintln!("warning: {warning}");
+    }
+
+    let (count, clk) = replay(&movie, &rom)?;
+    if count == 0 {
+        eprintln!("warning: {} has no checkpoints", movie_path.display());
+    }
+    println!(
+        "{}: {} checkpoints matched over {clk} cycles",
+        movie_path.display(),
+        count
+    );
+    Ok(())
+}
+
+// Returns the number of checkpoints matched and the clk the replay ended at
+fn replay(movie: &Movie, rom: &[u8]) -> Result<(usize, u64)> {
+    // Checkpoints before the start state can't be checked
+    let start = movie.start.as_ref().map_or(0, |state| state.clk);
+    let mut checkpoints = movie
+        .checkpoints
+        .iter()
+        .copied()
+        .filter(|(clk, _)| *clk > start)
+        .collect::<Vec<_>>();
+    checkpoints.sort_by_key(|(clk, _)| *clk);
+    let count = checkpoints.len();
+    let ticks_per_timer = movie.header.ticks_per_timer;
+    let events = movie.cycle_events(ticks_per_timer);
+    let resets = movie.cycle_resets(ticks_per_timer);
+    let end = checkpoints
+        .iter()
+        .map(|(clk, _)| *clk)
+        .chain(events.last().map(|(clk, _)| *clk))
+        .chain(resets.last().copied())
+        .max()
+        .unwrap_or_default();
+
+    let rng = ChaCha12Rng::seed_from_u64(movie.header.seed);
+    let cpu = SimpleCpu::new(movie.header.clock_frequency, rng)
+        .with_ticks_per_timer(ticks_per_timer)
+        .with_rng_position(movie.header.rng_position);
+    let mut chip8 = Chip8::new(cpu, events)
+        .with_resets(resets)
+        .with_fault_policy(movie.header.fault_policy);
+    chip8.load(rom)?;
+    if let Some(state) = &movie.start {
+        chip8.load_state(state)?;
+    }
+
+    let mut checkpoints = checkpoints.into_iter().peekable();
+    let mut clk = chip8.clk()?;
+    while clk < end {
+        chip8
+            .step()
+            .wrap_err_with(|| format!("Replay failed at clk {clk}"))?;
+        clk = chip8.clk()?;
+
+        while let Some((_, expected)) = checkpoints.next_if(|(at, _)| *at == clk) {
+            let actual = chip8.save_state()?.checksum();
+            if actual != expected {
+                bail!("Replay diverged at clk {clk}: expected checksum {expected:016x}, got {actual:016x}");
+            }
+        }
+    }
+    Ok((count, clk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::{
+        input::{InputEvent, InputKind},
+        keypad::Key,
+        movie::MovieHeader,
+    };
+
+    // V0 = random, wait for a key into V1, then start over
+    const ROM: [u8; 6] = [0xC0, 0xFF, 0xF1, 0x0A, 0x12, 0x00];
+
+    fn press(clk: u64, key: char, kind: InputKind) -> (u64, InputEvent) {
+        let key = Key::try_from(key).unwrap();
+        (clk, InputEvent { key, kind })
+    }
+
+    // The movie of a run from `seed`, with a checkpoint at each of `clks`
+    fn record(seed: u64, events: Vec<(u64, InputEvent)>, clks: &[u64]) -> Movie {
+        let mut movie = Movie::new(MovieHeader::new(rom_hash(&ROM), seed, 500));
+        let rng = ChaCha12Rng::seed_from_u64(seed);
+        let mut chip8 = Chip8::new(SimpleCpu::new(500, rng), events.clone());
+        chip8.load(&ROM).unwrap();
+        for &clk in clks {
+            while chip8.clk().unwrap() < clk {
+                chip8.step().unwrap();
+            }
+            let checksum = chip8.save_state().unwrap().checksum();
+            movie.checkpoints.push((clk, checksum));
+        }
+        movie.events = events;
+        movie
+    }
+
+    fn events() -> Vec<(u64, InputEvent)> {
+        vec![
+            press(5, '7', InputKind::Press),
+            press(9, '7', InputKind::Release),
+            press(30, 'A', InputKind::Press),
+            press(34, 'A', InputKind::Release),
+        ]
+    }
+
+    #[test]
+    fn matches_its_own_recording() {
+        let movie = record(1, events(), &[10, 20, 40]);
+        assert_eq!(replay(&movie, &ROM).unwrap(), (3, 40));
+    }
+
+    #[test]
+    fn finds_where_a_replay_diverges() {
+        let mut movie = record(1, events(), &[10, 20, 40]);
+        movie.checkpoints[1].1 ^= 1;
+        let error = replay(&movie, &ROM).unwrap_err().to_string();
+        assert!(error.starts_with("Replay diverged at clk 20"), "{error}");
+
+        // Another seed draws another V0 from the first cycle
+        let mut movie = record(1, events(), &[10, 20, 40]);
+        movie.header.seed = 2;
+        let error = replay(&movie, &ROM).unwrap_err().to_string();
+        assert!(error.starts_with("Replay diverged at clk 10"), "{error}");
+
+        // Another key shows at the first checkpoint after it
+        let mut movie = record(1, events(), &[10, 20, 40]);
+        movie.events[2] = press(30, 'B', InputKind::Press);
+        let error = replay(&movie, &ROM).unwrap_err().to_string();
+        assert!(error.starts_with("Replay diverged at clk 40"), "{error}");
+    }
+}